@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, Permissions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde_derive::Serialize;
+
+use crate::core::{EmptyResult, GenericResult};
+use crate::providers::{dropbox, google_drive, yandex_disk, oauth};
+
+const REQUEST_TIMEOUT: u64 = 10;
+
+#[derive(Clone, Copy)]
+pub enum OauthProvider {
+    Dropbox,
+    GoogleDrive,
+    YandexDisk,
+}
+
+impl OauthProvider {
+    pub fn from_name(name: &str) -> Option<OauthProvider> {
+        Some(match name {
+            "dropbox" => OauthProvider::Dropbox,
+            "google-drive" => OauthProvider::GoogleDrive,
+            "yandex-disk" => OauthProvider::YandexDisk,
+            _ => return None,
+        })
+    }
+
+    // Provider name as it's specified in the configuration file
+    fn name(self) -> &'static str {
+        match self {
+            OauthProvider::Dropbox => "dropbox",
+            OauthProvider::GoogleDrive => "google-drive",
+            OauthProvider::YandexDisk => "yandex-disk",
+        }
+    }
+
+    fn authorization_url(self) -> &'static str {
+        match self {
+            OauthProvider::Dropbox => dropbox::AUTHORIZATION_URL,
+            OauthProvider::GoogleDrive => google_drive::AUTHORIZATION_URL,
+            OauthProvider::YandexDisk => yandex_disk::AUTHORIZATION_URL,
+        }
+    }
+
+    fn token_url(self) -> String {
+        let endpoint = match self {
+            OauthProvider::Dropbox => dropbox::OAUTH_ENDPOINT,
+            OauthProvider::GoogleDrive => google_drive::OAUTH_ENDPOINT,
+            OauthProvider::YandexDisk => yandex_disk::OAUTH_ENDPOINT,
+        };
+        format!("{}/token", endpoint)
+    }
+
+    // Parameters which are required to get a long-living refresh token
+    fn authorization_params(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            OauthProvider::Dropbox => &[("token_access_type", "offline")],
+            OauthProvider::GoogleDrive => &[
+                ("scope", "https://www.googleapis.com/auth/drive"),
+                ("access_type", "offline"),
+                ("prompt", "consent"),
+            ],
+            OauthProvider::YandexDisk => &[],
+        }
+    }
+}
+
+pub fn authorize(
+    provider: OauthProvider, client_id: &str, client_secret: &str, port: u16, token_url: Option<&str>,
+    output_path: Option<&Path>,
+) -> EmptyResult {
+    let authorization = Authorization::new(provider, client_id, client_secret, port, token_url)?;
+
+    // Logging may be disabled when the configuration is written to stdout
+    eprintln!(
+        "Make sure that {} is allowed as a redirect URI in the application settings and open the following URL to authorize the application:\n{}",
+        authorization.redirect_uri(), authorization.url());
+
+    let config = authorization.wait()?;
+
+    match output_path {
+        Some(path) => {
+            write_config(path, &config).map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+            info!("The provider configuration has been written to {:?}.", path);
+        },
+        None => print!("{}", config),
+    }
+
+    Ok(())
+}
+
+// The configuration contains secrets, so the file mode is changed before writing to the file, because
+// the mode passed to open() is applied only to newly created files.
+fn write_config(path: &Path, config: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).truncate(false).write(true).mode(0o600).open(path)?;
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.set_len(0)?;
+    file.write_all(config.as_bytes())
+}
+
+// Implements OAuth authorization code flow with a loopback redirect URI
+pub struct Authorization {
+    provider: OauthProvider,
+    client_id: String,
+    client_secret: String,
+    token_url: String,
+
+    listener: TcpListener,
+    redirect_uri: String,
+    state: String,
+}
+
+impl Authorization {
+    pub fn new(
+        provider: OauthProvider, client_id: &str, client_secret: &str, port: u16, token_url: Option<&str>,
+    ) -> GenericResult<Authorization> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!(
+            "Unable to listen on 127.0.0.1:{}: {}", port, e))?;
+        let port = listener.local_addr()?.port();
+
+        let mut state = [0; 16];
+        File::open("/dev/urandom").and_then(|mut file| file.read_exact(&mut state)).map_err(|e| format!(
+            "Unable to generate random state: {}", e))?;
+
+        Ok(Authorization {
+            provider,
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            token_url: token_url.map(ToOwned::to_owned).unwrap_or_else(|| provider.token_url()),
+
+            listener,
+            redirect_uri: format!("http://127.0.0.1:{}/", port),
+            state: hex::encode(state),
+        })
+    }
+
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    pub fn url(&self) -> String {
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", &self.client_id),
+            ("redirect_uri", &self.redirect_uri),
+            ("state", &self.state),
+        ];
+        params.extend(self.provider.authorization_params());
+
+        format!("{}?{}", self.provider.authorization_url(), serde_urlencoded::to_string(params).unwrap())
+    }
+
+    // Waits for the redirect and returns provider configuration for the configuration file
+    pub fn wait(&self) -> GenericResult<String> {
+        let code = self.receive_code()?;
+
+        info!("Obtaining a refresh token...");
+        let refresh_token = oauth::obtain_refresh_token(
+            &self.token_url, &self.client_id, &self.client_secret, &code, &self.redirect_uri,
+        ).map_err(|e| format!("Failed to obtain a refresh token: {}", e))?;
+
+        #[derive(Serialize)]
+        struct Config<'a> {
+            provider: ProviderConfig<'a>,
+        }
+
+        #[derive(Serialize)]
+        struct ProviderConfig<'a> {
+            name: &'a str,
+            client_id: &'a str,
+            client_secret: &'a str,
+            refresh_token: &'a str,
+        }
+
+        Ok(serde_yaml::to_string(&Config {
+            provider: ProviderConfig {
+                name: self.provider.name(),
+                client_id: &self.client_id,
+                client_secret: &self.client_secret,
+                refresh_token: &refresh_token,
+            },
+        })?)
+    }
+
+    fn receive_code(&self) -> GenericResult<String> {
+        info!("Waiting for authorization redirect to {}...", self.redirect_uri);
+
+        for stream in self.listener.incoming() {
+            let mut stream = stream?;
+            stream.set_read_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT)))?;
+            stream.set_write_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT)))?;
+
+            let params = match read_request(&mut stream) {
+                Ok(Some(params)) => params,
+                Ok(None) => {
+                    send_response(&mut stream, "404 Not Found", "Not found.");
+                    continue;
+                },
+                Err(err) => {
+                    warn!("Got an invalid HTTP request: {}.", err);
+                    continue;
+                },
+            };
+
+            if params.get("state") != Some(&self.state) {
+                warn!("Got a redirect with an invalid state. Ignore it.");
+                send_response(&mut stream, "400 Bad Request", "Invalid state.");
+                continue;
+            }
+
+            if let Some(error) = params.get("error") {
+                send_response(&mut stream, "200 OK", "Authorization failed.");
+                return match params.get("error_description") {
+                    Some(description) => Err!("Authorization failed: {} ({})", description, error),
+                    None => Err!("Authorization failed: {}", error),
+                };
+            }
+
+            let Some(code) = params.get("code") else {
+                send_response(&mut stream, "400 Bad Request", "Authorization code is missing.");
+                continue;
+            };
+
+            send_response(&mut stream, "200 OK", "Authorization completed. You can close this page.");
+            return Ok(code.clone());
+        }
+
+        unreachable!()
+    }
+}
+
+// Returns query parameters of the redirect request or None for requests to other paths
+fn read_request(stream: &mut TcpStream) -> GenericResult<Option<HashMap<String, String>>> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    debug!("Got HTTP request: {}", request_line.trim_end());
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (Some("GET"), Some(target)) = (parts.next(), parts.next()) else {
+        return Err!("Unsupported request: {:?}", request_line.trim_end());
+    };
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if path != "/" {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_urlencoded::from_str(query)?))
+}
+
+fn send_response(stream: &mut TcpStream, status: &str, message: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, message.len(), message);
+
+    if let Err(err) = stream.write_all(response.as_bytes()) {
+        warn!("Failed to send HTTP response: {}.", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+
+    use indoc::indoc;
+    use maplit::hashmap;
+
+    use super::*;
+
+    #[test]
+    fn authorization() {
+        let token_server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let token_url = format!("http://{}/token", token_server.local_addr().unwrap());
+
+        let token_server = thread::spawn(move || {
+            let (stream, _) = token_server.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                let line = line.trim_end().to_lowercase();
+                if line.is_empty() {
+                    break;
+                } else if let Some(value) = line.strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let response = r#"{"access_token": "access-token", "expires_in": 3600, "refresh_token": "refresh-token"}"#;
+            write!(reader.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(), response).unwrap();
+
+            serde_urlencoded::from_bytes::<HashMap<String, String>>(&body).unwrap()
+        });
+
+        let authorization = Authorization::new(
+            OauthProvider::Dropbox, "client-id", "client-secret", 0, Some(&token_url)).unwrap();
+
+        let url = authorization.url();
+        let (_, query) = url.split_once('?').unwrap();
+        let params: HashMap<String, String> = serde_urlencoded::from_str(query).unwrap();
+        assert_eq!(params["redirect_uri"], authorization.redirect_uri());
+
+        let redirect_address = authorization.listener.local_addr().unwrap();
+        let state = params["state"].clone();
+
+        let browser = thread::spawn(move || {
+            let mut responses = Vec::new();
+
+            for target in ["/favicon.ico", &format!("/?code=auth-code&state={}", state)] {
+                let mut stream = TcpStream::connect(redirect_address).unwrap();
+                write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, redirect_address).unwrap();
+
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                responses.push(response.lines().next().unwrap().to_owned());
+            }
+
+            responses
+        });
+
+        let config = authorization.wait().unwrap();
+
+        assert_eq!(browser.join().unwrap(), ["HTTP/1.1 404 Not Found", "HTTP/1.1 200 OK"]);
+        assert_eq!(token_server.join().unwrap(), hashmap! {
+            "client_id".to_owned() => "client-id".to_owned(),
+            "client_secret".to_owned() => "client-secret".to_owned(),
+            "code".to_owned() => "auth-code".to_owned(),
+            "redirect_uri".to_owned() => authorization.redirect_uri().to_owned(),
+            "grant_type".to_owned() => "authorization_code".to_owned(),
+        });
+
+        assert_eq!(config, indoc!("
+            provider:
+              name: dropbox
+              client_id: client-id
+              client_secret: client-secret
+              refresh_token: refresh-token
+        ").trim_start());
+    }
+
+    #[test]
+    fn config_writing() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.yaml");
+
+        fs::write(&path, "some long old contents").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+
+        write_config(&path, "config").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "config");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -1,22 +1,27 @@
 use std::collections::{HashMap, HashSet};
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, SeekFrom, BufWriter, Seek};
-use std::os::unix::fs::OpenOptionsExt;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
 use std::path::{Path, PathBuf, Component};
+use std::sync::Arc;
 
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 use rayon::prelude::*;
-use tar::Header;
-use zstd::stream::write::Encoder;
+use serde_derive::Serialize;
+use tar::{EntryType, Header};
 
 use crate::config::BackupConfig;
 use crate::core::{EmptyResult, GenericResult};
 use crate::storage::{Storage, BackupGroup, Backup};
-use crate::storage::metadata::{MetadataItem, Fingerprint, MetadataWriter};
-use crate::util::{self, hash::Hash};
-use crate::util::file_reader::{FileReader, EMPTY_FILE_HASH};
+use crate::storage::metadata::{Chunk, MetadataItem, Fingerprint, MetadataWriter};
+use crate::util::{self, hash::Hash, sparse, xattr::{self, Xattrs}};
+use crate::util::chunker;
 
-type Archive = tar::Builder<BufWriter<Encoder<'static, File>>>;
+use super::config::ChunkingConfig;
+use super::data_writer::DataWriter;
+use super::hasher::{FileData, FileHasher, FileState, FileStream, LastState};
+
+type Archive = tar::Builder<DataWriter>;
 
 pub struct BackupInstance {
     path: PathBuf,
@@ -26,7 +31,33 @@ pub struct BackupInstance {
     data: Option<Archive>,
 
     extern_hashes: HashSet<Hash>,
-    last_state: Option<HashMap<PathBuf, FileState>>
+    // Sizes of the extern files: a changed file may turn out to be a duplicate only if it has one of them
+    extern_sizes: HashSet<u64>,
+    extern_chunks: HashSet<Hash>,
+    chunking: Option<ChunkingConfig>,
+    hasher: Arc<FileHasher>,
+    hard_links: HashMap<(u64, u64), HardLinkTarget>,
+    stats: BackupStats,
+}
+
+// The first backed up path of a file with multiple hard links
+struct HardLinkTarget {
+    path: PathBuf,
+    hash: Hash,
+    size: u64,
+    // Chunks of the target which are extern for its links
+    chunks: Option<Vec<Chunk>>,
+}
+
+#[derive(Default, Serialize)]
+pub struct BackupStats {
+    pub files: usize,
+    pub directories: usize,
+    pub symlinks: usize,
+    pub hard_links: usize,
+    pub special_files: usize,
+    pub new_size: u64,
+    pub deduplicated_size: u64,
 }
 
 impl BackupInstance {
@@ -40,7 +71,12 @@ impl BackupInstance {
             data: None,
 
             extern_hashes: HashSet::new(),
-            last_state: None,
+            extern_sizes: HashSet::new(),
+            extern_chunks: HashSet::new(),
+            chunking: config.chunking.clone(),
+            hasher: Arc::new(FileHasher::new(None)),
+            hard_links: HashMap::new(),
+            stats: BackupStats::default(),
         };
 
         let backup_path = instance.temp_path.as_ref().unwrap();
@@ -48,114 +84,385 @@ impl BackupInstance {
         let metadata_path = backup_path.join(Backup::METADATA_NAME);
         instance.metadata = Some(MetadataWriter::new(
             create_file(&metadata_path).map_err(|e| format!(
-                "Failed to create {:?}: {}", metadata_path, e))?
+                "Failed to create {:?}: {}", metadata_path, e))?,
+            config.compression.metadata_level,
         ));
 
         let data_path = backup_path.join(Backup::DATA_NAME);
         let data_file = create_file(&data_path).map_err(|e| format!(
             "Failed to create {:?}: {}", data_path, e))?;
 
-        instance.data = Some(tar::Builder::new(BufWriter::with_capacity(
-            Encoder::<File>::recommended_input_size(),
-            Encoder::new(data_file, 10)?
-        )));
+        instance.data = Some(tar::Builder::new(DataWriter::new(data_file, &config.compression)?));
 
-        let (extern_hashes, last_state, ok) = load_backups_metadata(storage, &group);
+        let (extern_hashes, extern_sizes, extern_chunks, last_state, ok) = load_backups_metadata(storage, &group);
         instance.extern_hashes = extern_hashes;
-        instance.last_state = last_state;
+        instance.extern_sizes = extern_sizes;
+        instance.extern_chunks = extern_chunks;
+        instance.hasher = Arc::new(FileHasher::new(last_state));
 
         Ok((instance, ok))
     }
 
-    pub fn add_directory(&mut self, path: &Path, metadata: &fs::Metadata) -> EmptyResult {
-        let mut header = tar_header(metadata);
-        Ok(self.data().append_data(&mut header, tar_path(path)?, io::empty())?)
+    // Creates an instance which doesn't write anything, but only collects statistics of what would
+    // be backed up
+    pub fn dry_run(config: &BackupConfig, storage: &Storage) -> GenericResult<(BackupInstance, bool)> {
+        let (groups, _ok) = storage.get_backup_groups(false)?;
+
+        let (extern_hashes, extern_sizes, extern_chunks, last_state, ok) = match groups.last() {
+            Some(group) if group.backups.len() < config.max_backups_per_group => {
+                info!("Using {:?} backup group.", group.name);
+                load_backups_metadata(storage, group)
+            },
+            _ => {
+                info!("A new backup group would be created.");
+                (HashSet::new(), HashSet::new(), HashSet::new(), None, true)
+            },
+        };
+
+        Ok((BackupInstance {
+            path: PathBuf::new(),
+            temp_path: None,
+
+            metadata: None,
+            data: None,
+
+            extern_hashes,
+            extern_sizes,
+            extern_chunks,
+            chunking: config.chunking.clone(),
+            hasher: Arc::new(FileHasher::new(last_state)),
+            hard_links: HashMap::new(),
+            stats: BackupStats::default(),
+        }, ok))
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.data.is_none()
+    }
+
+    pub fn take_stats(&mut self) -> BackupStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    pub fn hasher(&self) -> Arc<FileHasher> {
+        self.hasher.clone()
+    }
+
+    pub fn add_directory(&mut self, path: &Path, metadata: &fs::Metadata, xattrs: &Xattrs) -> EmptyResult {
+        let archive_path = tar_path(path)?;
+        self.stats.directories += 1;
+
+        if let Some(data) = self.data.as_mut() {
+            let mut header = tar_header(metadata);
+            xattr::append_pax(data, xattrs)?;
+            data.append_data(&mut header, archive_path, io::empty())?;
+        }
+
+        Ok(())
     }
 
-    pub fn add_file(&mut self, path: &Path, fs_metadata: &fs::Metadata, mut file: File) -> EmptyResult {
+    pub fn add_file(
+        &mut self, path: &Path, fs_metadata: &fs::Metadata, xattrs: &Xattrs, file: File,
+        file_data: FileData, compress: bool,
+    ) -> EmptyResult {
+        let file_id = (fs_metadata.dev(), fs_metadata.ino());
+        let hard_link = fs_metadata.nlink() > 1;
+
+        if hard_link && self.hard_links.contains_key(&file_id) {
+            return self.add_hard_link(path, fs_metadata, file_id);
+        }
+
         let archive_path = tar_path(path)?;
         let mut header = tar_header(fs_metadata);
 
         let fingerprint = Fingerprint::new(fs_metadata);
         let size = fs_metadata.len();
 
-        let (hash, size, unique) = if let Some((hash, size)) = self.deduplicate(path, &mut file, &fingerprint, size)? {
-            header.set_size(0);
-            self.data().append_data(&mut header, archive_path, io::empty())?;
-            (hash, size, false)
-        } else {
-            let mut file_reader = FileReader::new(&mut file, size);
-            self.data().append_data(&mut header, archive_path, &mut file_reader)?;
+        let chunk_size = self.chunking.as_ref()
+            .filter(|chunking| size >= chunking.min_file_size)
+            .map(|chunking| chunking.chunk_size);
 
-            let (bytes_read, hash) = file_reader.consume();
-            if bytes_read != size {
-                warn!("{:?} has been truncated during backup.", path);
-            }
+        let (hash, size, unique, chunks) = match file_data {
+            FileData::Unchanged {hash, size, chunks} => {
+                self.add_extern_file(archive_path, &mut header, xattrs, size)?;
 
-            self.extern_hashes.insert(hash.clone());
-            (hash, bytes_read, true)
+                // All chunks of unchanged file are stored in the previous backups
+                let chunks = chunks.map(|chunks| chunks.into_iter().map(|chunk| Chunk {
+                    unique: false, ..chunk
+                }).collect());
+
+                (hash, size, false, chunks)
+            },
+
+            FileData::Buffered {hash, size: bytes_read, buffer} => {
+                if self.extern_hashes.contains(&hash) {
+                    debug!("Deduplicate {:?} by its hash.", path);
+                    self.add_extern_file(archive_path, &mut header, xattrs, bytes_read)?;
+                    (hash, bytes_read, false, None)
+                } else if let Some(chunk_size) = chunk_size {
+                    if bytes_read != size {
+                        warn!("{:?} has been truncated during backup.", path);
+                    }
+                    let mut stream = FileStream::new_chunked(buffer, chunk_size);
+                    let (size, chunks) = self.add_chunked_file(
+                        archive_path, &mut header, xattrs, &mut stream, compress)?;
+                    (stream.finish()?.1, size, false, Some(chunks))
+                } else {
+                    if let Some(data) = self.data.as_mut() {
+                        data.get_mut().set_compression(compress)?;
+                        xattr::append_pax(data, xattrs)?;
+                        data.append_data(&mut header, archive_path, buffer.data())?;
+                    }
+                    let (hash, size, unique) = self.add_unique_file(path, size, bytes_read, hash);
+                    (hash, size, unique, None)
+                }
+            },
+
+            // Chunks of the file are deduplicated while it's being read
+            FileData::Unread if let Some(chunk_size) = chunk_size => {
+                let regions = get_sparse_regions(path, &file, fs_metadata)?;
+                let mut stream = FileStream::new(file, regions, size, Some(chunk_size));
+
+                let (chunked_size, chunks) = self.add_chunked_file(
+                    archive_path, &mut header, xattrs, &mut stream, compress)?;
+
+                let (bytes_read, hash) = stream.finish()?;
+                if bytes_read != size {
+                    warn!("{:?} has been truncated during backup.", path);
+                }
+
+                (hash, chunked_size, false, Some(chunks))
+            },
+
+            // The file is written to the archive while being hashed and then rolled back if it turns out
+            // to be a duplicate. Each rollback point starts a new compression frame, so it's set only when
+            // the backups have a unique file of the same size.
+            FileData::Unread => {
+                let regions = get_sparse_regions(path, &file, fs_metadata)?;
+                let mut stream = FileStream::new(file, regions.clone(), size, None);
+
+                let checkpoint = match self.data.as_mut() {
+                    Some(data) => {
+                        data.get_mut().set_compression(compress)?;
+
+                        let checkpoint = if self.extern_sizes.contains(&size) {
+                            Some(data.get_mut().checkpoint()?)
+                        } else {
+                            None
+                        };
+
+                        xattr::append_pax(data, xattrs)?;
+
+                        if let Some(ref regions) = regions {
+                            sparse::append(data, &mut header, archive_path, size, regions, &mut stream)?;
+                        } else {
+                            data.append_data(&mut header, archive_path, &mut stream)?;
+                        }
+
+                        checkpoint
+                    },
+                    None => {
+                        io::copy(&mut stream, &mut io::sink())?;
+                        None
+                    },
+                };
+
+                let (bytes_read, hash) = stream.finish()?;
+
+                // A truncated file may turn out to be a duplicate without a rollback point, so it's stored
+                // as a unique one.
+                if self.extern_hashes.contains(&hash) && (checkpoint.is_some() || self.is_dry_run()) {
+                    debug!("Deduplicate {:?} by its hash.", path);
+                    if let Some(checkpoint) = checkpoint {
+                        self.data.as_mut().unwrap().get_mut().rollback(checkpoint)?;
+                    }
+                    if regions.is_some() {
+                        sparse::to_regular(&mut header, bytes_read);
+                    }
+                    self.add_extern_file(archive_path, &mut header, xattrs, bytes_read)?;
+                    (hash, bytes_read, false, None)
+                } else {
+                    let (hash, size, unique) = self.add_unique_file(path, size, bytes_read, hash);
+                    (hash, size, unique, None)
+                }
+            },
         };
 
-        let metadata = MetadataItem::new(path, size, hash, fingerprint, unique)?;
-        self.metadata.as_mut().unwrap().write(&metadata)?;
+        if hard_link {
+            self.hard_links.insert(file_id, HardLinkTarget {
+                path: path.to_owned(),
+                hash: hash.clone(),
+                size,
+                chunks: chunks.as_ref().map(|chunks| chunks.iter().map(|chunk| Chunk {
+                    unique: false, ..chunk.clone()
+                }).collect()),
+            });
+        }
+
+        let metadata = match chunks {
+            Some(chunks) => MetadataItem::new_chunked(path, size, hash, fingerprint, chunks)?,
+            None => MetadataItem::new(path, size, hash, fingerprint, unique)?,
+        };
+        if let Some(writer) = self.metadata.as_mut() {
+            writer.write(&metadata)?;
+        }
+        self.stats.files += 1;
 
         Ok(())
     }
 
-    pub fn add_symlink(&mut self, path: &Path, metadata: &fs::Metadata, target: &Path) -> EmptyResult {
-        let mut header = tar_header(metadata);
-        Ok(self.data().append_link(&mut header, tar_path(path)?, target)?)
+    // Adds a hard link which has been detected by the walker. Returns false if its target hasn't been backed
+    // up (failed to be read for example).
+    pub fn add_known_hard_link(&mut self, path: &Path, fs_metadata: &fs::Metadata) -> GenericResult<bool> {
+        let file_id = (fs_metadata.dev(), fs_metadata.ino());
+        if !self.hard_links.contains_key(&file_id) {
+            return Ok(false);
+        }
+
+        self.add_hard_link(path, fs_metadata, file_id)?;
+        Ok(true)
     }
 
-    pub fn finish(mut self) -> EmptyResult {
-        debug!("Fsyncing...");
+    fn add_hard_link(&mut self, path: &Path, fs_metadata: &fs::Metadata, file_id: (u64, u64)) -> EmptyResult {
+        let target = &self.hard_links[&file_id];
+        let archive_path = tar_path(path)?;
+        debug!("{:?} is a hard link to {:?}.", path, target.path);
 
-        self.metadata.take().unwrap().finish()?.sync_all()?;
-        self.data.take().unwrap().into_inner()?
-            .into_inner().map_err(|e| e.into_error())?.finish()?
-            .sync_all()?;
+        if let Some(data) = self.data.as_mut() {
+            let mut header = tar_header(fs_metadata);
+            header.set_entry_type(EntryType::Link);
+            header.set_size(0);
+            data.append_link(&mut header, archive_path, tar_path(&target.path)?)?;
+        }
 
-        let temp_path = self.temp_path.clone().unwrap();
-        let parent_path = temp_path.parent().unwrap();
+        // Hard links carry the target's chunks, so they remain restorable from the next backups when the
+        // target is gone
+        let metadata = MetadataItem::new_hard_link(
+            path, target.size, target.hash.clone(), Fingerprint::new(fs_metadata), target.chunks.clone())?;
+        if let Some(writer) = self.metadata.as_mut() {
+            writer.write(&metadata)?;
+        }
+        self.stats.hard_links += 1;
 
-        util::sys::fsync_directory(&temp_path)?;
-        fs::rename(&temp_path, &self.path)?;
-        self.temp_path = None;
-        util::sys::fsync_directory(parent_path)?;
+        Ok(())
+    }
+
+    pub fn add_symlink(
+        &mut self, path: &Path, metadata: &fs::Metadata, target: &Path, xattrs: &Xattrs,
+    ) -> EmptyResult {
+        let archive_path = tar_path(path)?;
+        self.stats.symlinks += 1;
+
+        if let Some(data) = self.data.as_mut() {
+            let mut header = tar_header(metadata);
+            xattr::append_pax(data, xattrs)?;
+            data.append_link(&mut header, archive_path, target)?;
+        }
 
         Ok(())
     }
 
-    fn deduplicate(
-        &mut self, path: &Path, file: &mut File, fingerprint: &Fingerprint, size: u64,
-    ) -> GenericResult<Option<(Hash, u64)>> {
-        if size == 0 {
-            debug!("{:?} has zero size.", path);
-            return Ok(Some((EMPTY_FILE_HASH.clone(), size)))
+    pub fn add_special(&mut self, path: &Path, metadata: &fs::Metadata, xattrs: &Xattrs) -> EmptyResult {
+        let archive_path = tar_path(path)?;
+        self.stats.special_files += 1;
+
+        if let Some(data) = self.data.as_mut() {
+            let mut header = tar_header(metadata);
+
+            if !metadata.file_type().is_fifo() {
+                let device = metadata.rdev() as libc::dev_t;
+                header.set_device_major(libc::major(device) as u32)?;
+                header.set_device_minor(libc::minor(device) as u32)?;
+            }
+
+            xattr::append_pax(data, xattrs)?;
+            data.append_data(&mut header, archive_path, io::empty())?;
+        }
+
+        Ok(())
+    }
+
+    fn add_extern_file(
+        &mut self, archive_path: &Path, header: &mut Header, xattrs: &Xattrs, size: u64,
+    ) -> EmptyResult {
+        if let Some(data) = self.data.as_mut() {
+            header.set_size(0);
+            xattr::append_pax(data, xattrs)?;
+            data.append_data(header, archive_path, io::empty())?;
+        }
+        self.stats.deduplicated_size += size;
+        Ok(())
+    }
+
+    // Stores the file as a data-less entry followed by its unique chunks
+    fn add_chunked_file(
+        &mut self, archive_path: &Path, header: &mut Header, xattrs: &Xattrs, stream: &mut FileStream,
+        compress: bool,
+    ) -> GenericResult<(u64, Vec<Chunk>)> {
+        if let Some(data) = self.data.as_mut() {
+            data.get_mut().set_compression(compress)?;
+            header.set_size(0);
+            xattr::append_pax(data, xattrs)?;
+            data.append_data(header, archive_path, io::empty())?;
         }
 
-        if let Some(last_state) = self.last_state.as_ref().and_then(|states| states.get(path)) {
-            if *fingerprint == last_state.fingerprint {
-                debug!("{:?} hasn't been changed.", path);
-                return Ok(Some((last_state.hash.clone(), size)));
+        let mut chunks = Vec::new();
+        let mut size = 0;
+
+        while let Some((data, hash)) = stream.next_chunk()? {
+            let chunk_size = data.len() as u64;
+            let unique = self.extern_chunks.insert(hash.clone());
+
+            if unique {
+                if let Some(archive) = self.data.as_mut() {
+                    let mut header = Header::new_gnu();
+                    header.set_entry_type(EntryType::Continuous);
+                    header.set_mode(0o600);
+                    header.set_size(chunk_size);
+                    archive.append_data(&mut header, chunker::get_chunk_path(&hash), data.as_slice())?;
+                }
+                self.stats.new_size += chunk_size;
+            } else {
+                self.stats.deduplicated_size += chunk_size;
             }
+
+            size += chunk_size;
+            chunks.push(Chunk {hash, size: chunk_size, unique});
         }
 
-        let mut file_reader = FileReader::new(file, size);
-        io::copy(&mut file_reader, &mut io::sink())?;
-        let (bytes_read, hash) = file_reader.consume();
-        file.seek(SeekFrom::Start(0))?;
+        Ok((size, chunks))
+    }
 
-        if self.extern_hashes.contains(&hash) {
-            debug!("Deduplicate {:?} by its hash.", path);
-            return Ok(Some((hash, bytes_read)))
+    fn add_unique_file(&mut self, path: &Path, size: u64, bytes_read: u64, hash: Hash) -> (Hash, u64, bool) {
+        if bytes_read != size {
+            warn!("{:?} has been truncated during backup.", path);
         }
 
-        Ok(None)
+        self.extern_hashes.insert(hash.clone());
+        self.extern_sizes.insert(bytes_read);
+        self.stats.new_size += bytes_read;
+        (hash, bytes_read, true)
     }
 
-    fn data(&mut self) -> &mut Archive {
-        self.data.as_mut().unwrap()
+    pub fn finish(mut self) -> EmptyResult {
+        if self.is_dry_run() {
+            return Ok(());
+        }
+
+        debug!("Fsyncing...");
+
+        self.metadata.take().unwrap().finish()?.sync_all()?;
+        self.data.take().unwrap().into_inner()?.finish()?.sync_all()?;
+
+        let temp_path = self.temp_path.clone().unwrap();
+        let parent_path = temp_path.parent().unwrap();
+
+        util::sys::fsync_directory(&temp_path)?;
+        fs::rename(&temp_path, &self.path)?;
+        self.temp_path = None;
+        util::sys::fsync_directory(parent_path)?;
+
+        Ok(())
     }
 }
 
@@ -182,23 +489,33 @@ fn tar_path(path: &Path) -> GenericResult<&Path> {
         "An attempt to add an invalid path to data archive: {:?}", path))?)
 }
 
+fn get_sparse_regions(path: &Path, file: &File, metadata: &fs::Metadata) -> io::Result<Option<sparse::Regions>> {
+    if !sparse::is_sparse(metadata) {
+        return Ok(None);
+    }
+
+    let regions = sparse::get_data_regions(file, metadata.len())?;
+    if regions.is_some() {
+        debug!("{:?} is a sparse file.", path);
+    }
+
+    Ok(regions)
+}
+
 fn tar_header(metadata: &fs::Metadata) -> Header {
     let mut header = Header::new_gnu();
     header.set_metadata(metadata);
     header
 }
 
-struct FileState {
-    fingerprint: Fingerprint,
-    hash: Hash,
-}
-
 fn load_backups_metadata(storage: &Storage, group: &BackupGroup) -> (
-    HashSet<Hash>, Option<HashMap<PathBuf, FileState>>, bool,
+    HashSet<Hash>, HashSet<u64>, HashSet<Hash>, Option<LastState>, bool,
 ) {
     let backups = &group.backups;
     let results = backups.par_iter().enumerate().map(|(index, backup): (usize, &Backup)| {
         let mut hashes = HashSet::new();
+        let mut sizes = HashSet::new();
+        let mut chunk_hashes = HashSet::new();
         let mut last_state = if index == backups.len() - 1 {
             Some(HashMap::new())
         } else {
@@ -212,25 +529,37 @@ fn load_backups_metadata(storage: &Storage, group: &BackupGroup) -> (
                 last_state.insert(file.path.into(), FileState {
                     fingerprint: file.fingerprint,
                     hash: file.hash.clone(),
+                    chunks: file.chunks.clone(),
                 });
             }
 
             if file.unique {
                 hashes.insert(file.hash);
+                sizes.insert(file.size);
+            }
+
+            for chunk in file.chunks.into_iter().flatten() {
+                if chunk.unique {
+                    chunk_hashes.insert(chunk.hash);
+                }
             }
         }
 
-        Ok((hashes, last_state))
+        Ok((hashes, sizes, chunk_hashes, last_state))
     });
 
     let mut ok = true;
     let mut all_hashes = HashSet::new();
+    let mut all_sizes = HashSet::new();
+    let mut all_chunk_hashes = HashSet::new();
     let mut files_last_state = None;
 
     for (index, result) in results.collect::<Vec<GenericResult<_>>>().into_iter().enumerate() {
         match result {
-            Ok((hashes, last_state)) => {
+            Ok((hashes, sizes, chunk_hashes, last_state)) => {
                 all_hashes.extend(hashes);
+                all_sizes.extend(sizes);
+                all_chunk_hashes.extend(chunk_hashes);
                 files_last_state = last_state;
             },
             Err(e) => {
@@ -241,5 +570,5 @@ fn load_backups_metadata(storage: &Storage, group: &BackupGroup) -> (
         }
     }
 
-    (all_hashes, files_last_state, ok)
+    (all_hashes, all_sizes, all_chunk_hashes, files_last_state, ok)
 }
\ No newline at end of file
@@ -1,26 +1,36 @@
 use std::collections::HashSet;
-use std::fs::{self, Metadata, OpenOptions};
-use std::io::{self, ErrorKind};
-use std::os::unix::fs::{MetadataExt, OpenOptionsExt, FileTypeExt};
-use std::path::{Component, Path, PathBuf};
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
-use itertools::Itertools;
-use log::{debug, warn, error};
-use nix::errno::Errno;
-use nix::fcntl::OFlag;
+use globset::GlobSet;
+use log::{debug, info, warn, error};
+use serde_derive::Serialize;
 
 use crate::core::{EmptyResult, GenericError, GenericResult};
-use crate::util;
+use crate::util::logging::ThreadContext;
 
-use super::{BackupInstance, BackupConfig, BackupItemConfig, PathFilter};
+use super::{BackupInstance, BackupConfig, BackupItemConfig, BackupStats};
+use super::walker::{self, Entry, Walker};
+
+#[derive(Serialize)]
+pub struct ItemResult {
+    pub path: String,
+    #[serde(flatten)]
+    pub stats: BackupStats,
+    pub filtered_paths: Vec<PathBuf>,
+}
 
 pub struct Backuper<'a> {
     backup: BackupInstance,
     items: &'a Vec<BackupItemConfig>,
+    store: &'a GlobSet,
 
     roots: Vec<PathBuf>,
     root_parents: HashSet<PathBuf>,
+    filtered_paths: Vec<PathBuf>,
     ok: bool,
 }
 
@@ -29,20 +39,24 @@ impl Backuper<'_> {
         Ok(Backuper {
             backup,
             items: &config.items,
+            store: &config.compression.store,
             roots: Vec::new(),
             root_parents: HashSet::new(),
+            filtered_paths: Vec::new(),
             ok: true,
         })
     }
 
-    pub fn run(mut self) -> GenericResult<bool> {
+    pub fn run(mut self) -> GenericResult<(Vec<ItemResult>, bool)> {
+        let mut results = Vec::new();
+
         for item in self.items {
             if let Some(ref command) = item.before {
                 self.run_command(&item.path, "before", command)?;
             }
 
             let result = match self.prepare(item) {
-                Ok(path) => self.backup_path(&path, Path::new(""), true, &item.filter),
+                Ok(path) => self.backup_path(&path, item),
                 Err(err) => self.handle_path_error(Path::new(&item.path), err),
             };
 
@@ -52,10 +66,16 @@ impl Backuper<'_> {
 
             result?;
             after_result?;
+
+            results.push(ItemResult {
+                path: item.path.clone(),
+                stats: self.backup.take_stats(),
+                filtered_paths: mem::take(&mut self.filtered_paths),
+            });
         }
 
         self.backup.finish()?;
-        Ok(self.ok)
+        Ok((results, self.ok))
     }
 
     fn prepare(&mut self, item: &BackupItemConfig) -> GenericResult<PathBuf> {
@@ -72,6 +92,11 @@ impl Backuper<'_> {
     }
 
     fn run_command(&mut self, path: &str, name: &str, command: &str) -> EmptyResult {
+        if self.backup.is_dry_run() {
+            info!("Skipping `{}` command for {:?} in dry run mode.", name, path);
+            return Ok(());
+        }
+
         debug!("Executing `{}` command for {:?}...", name, path);
 
         match Command::new("bash").arg("-c").arg(command).status() {
@@ -89,214 +114,76 @@ impl Backuper<'_> {
         Ok(())
     }
 
-    fn backup_path(
-        &mut self, path: &Path, relative_path: &Path, top_level: bool, filter: &PathFilter,
-    ) -> EmptyResult {
-        debug!("Backing up {:?}...", path);
-
-        if let Err(err) = crate::storage::metadata::validate_path(path) {
-            return self.handle_path_error(path, err);
-        }
-
-        if top_level && !self.backup_parent_directories(path)? {
-            return Ok(());
-        }
-
-        let metadata = match fs::symlink_metadata(path) {
-            Ok(metadata) => metadata,
-            Err(err) => {
-                return self.handle_access_error(path, top_level, err, None);
-            },
-        };
-
-        let file_type = metadata.file_type();
-
-        if file_type.is_file() {
-            self.backup_file(path, top_level)?;
-        } else if file_type.is_dir() {
-            self.backup_directory(path, relative_path, top_level, filter, metadata)?;
-        } else if file_type.is_symlink() {
-            self.backup_symlink(path, top_level, metadata)?;
-        } else if !top_level && (
-            file_type.is_block_device() || file_type.is_char_device() ||
-            file_type.is_fifo() || file_type.is_socket()
-        ) {
-            warn!("Skipping {:?}: unsupported file type.", path);
-        } else {
-            return self.handle_path_error(path, "unsupported file type");
-        }
-
-        Ok(())
+    fn backup_path(&mut self, path: &Path, item: &BackupItemConfig) -> EmptyResult {
+        let (sender, receiver) = mpsc::sync_channel(Walker::MAX_PENDING_ENTRIES);
+        let walker = Walker::new(sender, self.backup.hasher(), mem::take(&mut self.root_parents));
+
+        // The walker is stopped by the receiver drop on error
+        let context = ThreadContext::current();
+        thread::scope(|scope| {
+            let walker = scope.spawn(|| {
+                let _context = ThreadContext::inherit(context);
+                walker.walk(path, item)
+            });
+            let result = self.process_entries(receiver, item.compress);
+            (self.root_parents, self.filtered_paths) = walker.join().unwrap();
+            result
+        })
     }
 
-    fn backup_parent_directories(&mut self, path: &Path) -> GenericResult<bool> {
-        let mut parent = PathBuf::new();
-
-        for (index, part) in path.components().dropping_back(1).enumerate() {
-            parent.push(part);
-
-            match part {
-                Component::RootDir if index == 0 => {
-                    continue;
-                },
-                Component::Normal(_) if index != 0 => {
-                },
-                _ => {
-                    self.handle_path_error(path, "invalid path")?;
-                    return Ok(false);
-                },
-            }
-
-            if self.root_parents.contains(&parent) {
-                continue
-            }
-
-            let metadata = match fs::symlink_metadata(&parent) {
-                Ok(metadata) => metadata,
-                Err(err) => {
-                    self.handle_path_error(path, err)?;
-                    return Ok(false);
-                },
-            };
-
-            if !metadata.is_dir() {
-                self.handle_path_error(path, format!(
-                    "{:?} has changed its type during the backup", parent))?;
-                return Ok(false);
-            }
-
-            self.backup.add_directory(&parent, &metadata).map_err(|e| format!(
-                "Failed to backup {:?}: {}", parent, e))?;
-
-            self.root_parents.insert(parent.clone());
+    fn process_entries(&mut self, entries: Receiver<Entry>, compress: bool) -> EmptyResult {
+        for entry in entries {
+            self.process_entry(entry, compress)?;
         }
-
-        Ok(true)
+        Ok(())
     }
 
-    fn backup_directory(
-        &mut self, path: &Path, relative_path: &Path, top_level: bool, filter: &PathFilter,
-        metadata: Metadata,
-    ) -> EmptyResult {
-        let entries = match fs::read_dir(path) {
-            Ok(entries) => entries,
-            Err(err) => {
-                return self.handle_access_error(path, top_level, err, Some(Errno::ENOTDIR));
+    fn process_entry(&mut self, entry: Entry, compress: bool) -> EmptyResult {
+        match entry {
+            Entry::Directory(path, metadata, xattrs) => {
+                self.backup.add_directory(&path, &metadata, &xattrs).map_err(|e| format!(
+                    "Failed to backup {:?}: {}", path, e))?;
             },
-        };
 
-        let mut names = Vec::new();
+            Entry::File(file) => {
+                let compress = compress && !file.path.file_name().is_some_and(|name| self.store.is_match(name));
 
-        for entry in entries {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(err) => {
-                    return self.handle_access_error(path, top_level, err, None);
-                },
-            };
-            names.push(entry.file_name());
-        }
-
-        if !top_level || !util::sys::is_root_path(path) {
-            self.backup.add_directory(path, &metadata).map_err(|e| format!(
-                "Failed to backup {:?}: {}", path, e))?;
-        }
+                self.backup.add_file(
+                    &file.path, &file.metadata, &file.xattrs, file.file, file.data, compress,
+                ).map_err(|e| format!("Failed to backup {:?}: {}", file.path, e))?;
+            },
 
-        // To make tests predictable
-        if cfg!(test) {
-            names.sort();
-        }
+            Entry::HardLink(path, metadata) => {
+                let added = self.backup.add_known_hard_link(&path, &metadata).map_err(|e| format!(
+                    "Failed to backup {:?}: {}", path, e))?;
 
-        for name in names {
-            let entry_path = path.join(&name);
-            let entry_relative_path = relative_path.join(&name);
-
-            match filter.check(&entry_relative_path) {
-                Ok(allow) => if allow {
-                    self.backup_path(&entry_path, &entry_relative_path, false, filter)?;
-                } else {
-                    debug!("Filtering out {:?}.", entry_path);
-                },
-                Err(err) => {
-                    self.handle_path_error(&entry_path, err)?;
+                // The file is backed up as a regular one if its first link hasn't been backed up
+                if !added {
+                    let entry = walker::prepare_file(path, false, &self.backup.hasher(), None)?;
+                    self.process_entry(entry, compress)?;
                 }
-            }
-        }
-
-        Ok(())
-    }
-
-    fn backup_file(&mut self, path: &Path, top_level: bool) -> EmptyResult {
-        let mut open_options = OpenOptions::new();
-        open_options.read(true).custom_flags(OFlag::O_NOFOLLOW.bits());
-
-        let file = match open_options.open(path) {
-            Ok(file) => file,
-            Err(err) => {
-                return self.handle_access_error(path, top_level, err, Some(Errno::ELOOP));
             },
-        };
 
-        let metadata = match file.metadata() {
-            Ok(metadata) => metadata,
-            Err(err) => {
-                return self.handle_access_error(path, top_level, err, None);
+            Entry::Symlink(path, metadata, target, xattrs) => {
+                self.backup.add_symlink(&path, &metadata, &target, &xattrs).map_err(|e| format!(
+                    "Failed to backup {:?}: {}", path, e))?;
             },
-        };
-
-        if !metadata.is_file() {
-            return self.handle_type_change(path, top_level);
-        }
-
-        let hard_links = metadata.nlink();
-        if hard_links > 1 {
-            warn!("{:?} has {} hard links.", path, hard_links - 1);
-        }
-
-        Ok(self.backup.add_file(path, &metadata, file).map_err(|e| format!(
-            "Failed to backup {:?}: {}", path, e))?)
-    }
 
-    fn backup_symlink(&mut self, path: &Path, top_level: bool, metadata: Metadata) -> EmptyResult {
-        let target = match fs::read_link(path) {
-            Ok(target) => target,
-            Err(err) => {
-                return self.handle_access_error(path, top_level, err, Some(Errno::EINVAL));
+            Entry::Special(path, metadata, xattrs) => {
+                self.backup.add_special(&path, &metadata, &xattrs).map_err(|e| format!(
+                    "Failed to backup {:?}: {}", path, e))?;
             },
-        };
-
-        Ok(self.backup.add_symlink(path, &metadata, &target).map_err(|e| format!(
-            "Failed to backup {:?}: {}", path, e))?)
-    }
 
-    fn handle_access_error(
-        &mut self, path: &Path, top_level: bool, err: io::Error, type_change_errno: Option<Errno>,
-    ) -> EmptyResult {
-        if let (Some(type_change_errno), Some(errno)) = (type_change_errno, err.raw_os_error()) {
-            if Errno::from_raw(errno) == type_change_errno {
-                return self.handle_type_change(path, top_level);
-            }
-        }
+            Entry::Pending(result) => {
+                let entry = result.recv().map_err(|_| "Backup worker thread has crashed")??;
+                self.process_entry(entry, compress)?;
+            },
 
-        if err.kind() == ErrorKind::NotFound && !top_level {
-            warn!("Failed to backup {:?}: it was deleted during backing up.", path);
-            return Ok(());
+            Entry::Warning(message) => warn!("{}.", message),
+            Entry::Error(message) => self.handle_error(format_args!("{}", message))?,
         }
 
-        self.handle_path_error(path, err)
-    }
-
-    fn handle_type_change(&mut self, path: &Path, top_level: bool) -> EmptyResult {
-        // We can't save format_args!() result to a variable, so have to use closure
-        let mut handle = |message| -> EmptyResult {
-            if top_level {
-                self.handle_error(message)
-            } else {
-                warn!("{}.", message);
-                Ok(())
-            }
-        };
-        handle(format_args!("Skipping {:?}: it changed its type during backing up", path))
+        Ok(())
     }
 
     fn handle_path_error<E: Into<GenericError>>(&mut self, path: &Path, err: E) -> EmptyResult {
@@ -1,7 +1,9 @@
 use std::path::PathBuf;
 
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::de::{Deserializer, Error};
 use serde_derive::{Serialize, Deserialize};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 use crate::core::GenericResult;
 
@@ -17,10 +19,100 @@ pub struct BackupConfig {
     pub max_backup_groups: usize,
     #[validate(range(min = 1))]
     pub max_backups_per_group: usize,
+    #[serde(default)]
+    #[validate(nested)]
+    pub compression: CompressionConfig,
+    // Enables chunk-level deduplication of large files
+    #[validate(nested)]
+    pub chunking: Option<ChunkingConfig>,
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct CompressionConfig {
+    #[serde(default = "default_level")]
+    #[validate(range(min = 1, max = 22))]
+    pub level: i32,
+    // Enables long distance matching with the specified window log
+    #[validate(range(min = 10, max = 31))]
+    pub long_window: Option<u32>,
+    #[serde(default)]
+    #[validate(range(max = 200))]
+    pub workers: u32,
+    #[serde(default = "default_metadata_level")]
+    #[validate(range(min = 1, max = 22))]
+    pub metadata_level: i32,
+    // File name globs of already compressed files which should be stored without compression
+    #[serde(default, deserialize_with = "deserialize_globs")]
+    pub store: GlobSet,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> CompressionConfig {
+        CompressionConfig {
+            level: default_level(),
+            long_window: None,
+            workers: 0,
+            metadata_level: default_metadata_level(),
+            store: GlobSet::empty(),
+        }
+    }
+}
+
+fn default_level() -> i32 {
+    10
+}
+
+fn default_metadata_level() -> i32 {
+    2
+}
+
+#[derive(Clone, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkingConfig {
+    // Smaller files are stored as a whole
+    #[serde(default = "default_chunking_min_file_size")]
+    #[validate(range(min = 1))]
+    pub min_file_size: u64,
+    // Average chunk size (the actual chunks are from 1/4 to 4 times of it)
+    #[serde(default = "default_chunk_size")]
+    #[validate(range(min = 64, max = 67108864), custom(function = "validate_chunk_size"))]
+    pub chunk_size: usize,
+}
+
+fn default_chunking_min_file_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_chunk_size() -> usize {
+    1024 * 1024
+}
+
+fn validate_chunk_size(size: usize) -> Result<(), ValidationError> {
+    if !size.is_power_of_two() {
+        return Err(ValidationError::new("power_of_two").with_message("must be a power of two".into()));
+    }
+    Ok(())
+}
+
+fn deserialize_globs<'de, D>(deserializer: D) -> Result<GlobSet, D::Error>
+    where D: Deserializer<'de>
+{
+    let globs: Vec<String> = serde::Deserialize::deserialize(deserializer)?;
+    let mut builder = GlobSetBuilder::new();
+
+    for glob in globs {
+        builder.add(GlobBuilder::new(&glob)
+            .case_insensitive(true).literal_separator(true)
+            .build().map_err(|e| D::Error::custom(format!("Invalid glob ({:?}): {}", glob, e)))?);
+    }
+
+    builder.build().map_err(D::Error::custom)
 }
 
 #[derive(Deserialize, Serialize, Validate)]
 #[serde(deny_unknown_fields)]
+#[validate(schema(function = "BackupItemConfig::validate_filesystems"))]
 pub struct BackupItemConfig {
     #[validate(length(min = 1))]
     pub path: String,
@@ -28,6 +120,19 @@ pub struct BackupItemConfig {
     pub filter: PathFilter,
     pub before: Option<String>,
     pub after: Option<String>,
+    // Allows to disable compression for items with already compressed data
+    #[serde(default = "default_compress")]
+    pub compress: bool,
+    // Don't descend into mount points of other filesystems
+    #[serde(default)]
+    pub one_file_system: bool,
+    // Descend only into mount points of the specified filesystem types
+    #[serde(default)]
+    pub filesystems: Vec<String>,
+}
+
+fn default_compress() -> bool {
+    true
 }
 
 impl BackupItemConfig {
@@ -38,4 +143,12 @@ impl BackupItemConfig {
         }
         Ok(path.canonicalize()?)
     }
+
+    fn validate_filesystems(&self) -> Result<(), ValidationError> {
+        if self.one_file_system && !self.filesystems.is_empty() {
+            return Err(ValidationError::new("filesystems").with_message(
+                "one_file_system and filesystems options are mutually exclusive".into()));
+        }
+        Ok(())
+    }
 }
\ No newline at end of file
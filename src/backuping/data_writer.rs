@@ -0,0 +1,229 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+use zstd::stream::write::Encoder;
+
+use super::CompressionConfig;
+
+type FrameEncoder = BufWriter<Encoder<'static, File>>;
+
+// Data archive writer which is able to roll back the data written after a checkpoint and to store
+// some of the data without compression.
+//
+// Compressed data can't be truncated at an arbitrary position, so each checkpoint starts a new zstd
+// frame (concatenated frames form a valid zstd stream). Uncompressed data is written as a separate
+// frame consisting of raw blocks.
+pub struct DataWriter {
+    frame: Option<Frame>,
+    level: i32,
+    long_window: Option<u32>,
+    workers: u32,
+}
+
+enum Frame {
+    Compressed(FrameEncoder),
+    Raw(RawFrameWriter),
+}
+
+impl DataWriter {
+    pub fn new(file: File, config: &CompressionConfig) -> io::Result<DataWriter> {
+        let mut writer = DataWriter {
+            frame: None,
+            level: config.level,
+            long_window: config.long_window,
+            workers: config.workers,
+        };
+        writer.frame.replace(writer.new_frame(file, true)?);
+        Ok(writer)
+    }
+
+    pub fn set_compression(&mut self, compress: bool) -> io::Result<()> {
+        if self.is_compressed()? != compress {
+            let file = self.finish_frame()?;
+            self.frame.replace(self.new_frame(file, compress)?);
+        }
+        Ok(())
+    }
+
+    pub fn checkpoint(&mut self) -> io::Result<u64> {
+        let compress = self.is_compressed()?;
+        let mut file = self.finish_frame()?;
+        let position = file.stream_position()?;
+        self.frame.replace(self.new_frame(file, compress)?);
+        Ok(position)
+    }
+
+    pub fn rollback(&mut self, checkpoint: u64) -> io::Result<()> {
+        let compress = self.is_compressed()?;
+        let mut file = self.finish_frame()?;
+        file.set_len(checkpoint)?;
+        file.seek(SeekFrom::Start(checkpoint))?;
+        self.frame.replace(self.new_frame(file, compress)?);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<File> {
+        self.finish_frame()
+    }
+
+    fn is_compressed(&self) -> io::Result<bool> {
+        Ok(matches!(self.frame.as_ref().ok_or_else(invalid_state_error)?, Frame::Compressed(_)))
+    }
+
+    fn new_frame(&self, file: File, compress: bool) -> io::Result<Frame> {
+        if !compress {
+            return Ok(Frame::Raw(RawFrameWriter::new(file)?));
+        }
+
+        let mut encoder = Encoder::new(file, self.level)?;
+
+        if let Some(window_log) = self.long_window {
+            encoder.long_distance_matching(true)?;
+            encoder.window_log(window_log)?;
+        }
+
+        if self.workers != 0 {
+            encoder.multithread(self.workers)?;
+        }
+
+        Ok(Frame::Compressed(BufWriter::with_capacity(
+            Encoder::<File>::recommended_input_size(), encoder)))
+    }
+
+    fn finish_frame(&mut self) -> io::Result<File> {
+        match self.frame.take().ok_or_else(invalid_state_error)? {
+            Frame::Compressed(encoder) => encoder.into_inner().map_err(|e| e.into_error())?.finish(),
+            Frame::Raw(writer) => writer.finish(),
+        }
+    }
+}
+
+impl Write for DataWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.frame.as_mut().ok_or_else(invalid_state_error)? {
+            Frame::Compressed(encoder) => encoder.write(buf),
+            Frame::Raw(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.frame.as_mut().ok_or_else(invalid_state_error)? {
+            Frame::Compressed(encoder) => encoder.flush(),
+            Frame::Raw(writer) => writer.flush(),
+        }
+    }
+}
+
+// The writer is left without frame after a failed frame switch
+fn invalid_state_error() -> io::Error {
+    io::Error::other("The data writer is in invalid state after a previous error")
+}
+
+// Writes zstd frame of raw (uncompressed) blocks (see RFC 8878)
+struct RawFrameWriter {
+    file: BufWriter<File>,
+    block: Vec<u8>,
+}
+
+impl RawFrameWriter {
+    const MAGIC_NUMBER: u32 = 0xFD2FB528;
+    const BLOCK_SIZE: usize = 128 * 1024;
+    const BLOCK_SIZE_LOG: u8 = 17;
+
+    fn new(file: File) -> io::Result<RawFrameWriter> {
+        let mut file = BufWriter::new(file);
+
+        file.write_all(&Self::MAGIC_NUMBER.to_le_bytes())?;
+        // Frame header descriptor: no single segment mode, content size, checksum and dictionary
+        file.write_all(&[0])?;
+        // Window descriptor: the minimal window which is able to hold the block
+        file.write_all(&[(Self::BLOCK_SIZE_LOG - 10) << 3])?;
+
+        Ok(RawFrameWriter {
+            file,
+            block: Vec::with_capacity(Self::BLOCK_SIZE),
+        })
+    }
+
+    fn write_block(&mut self, last: bool) -> io::Result<()> {
+        // Block header: last block flag, raw block type (0) and block size
+        let header = (self.block.len() as u32) << 3 | u32::from(last);
+        self.file.write_all(&header.to_le_bytes()[..3])?;
+        self.file.write_all(&self.block)?;
+        self.block.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<File> {
+        self.write_block(true)?;
+        self.file.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl Write for RawFrameWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.block.len() == Self::BLOCK_SIZE {
+            self.write_block(false)?;
+        }
+
+        let size = buf.len().min(Self::BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use rstest::rstest;
+
+    use crate::util::sys;
+
+    use super::*;
+
+    #[rstest(long_window, workers,
+        case(None, 0),
+        case(Some(28), 2),
+    )]
+    fn data_writer(long_window: Option<u32>, workers: u32) {
+        let config = CompressionConfig {long_window, workers, ..Default::default()};
+        let stored_data = vec![b'x'; 3 * RawFrameWriter::BLOCK_SIZE + 1];
+
+        let file = sys::create_temp_file().unwrap();
+        let mut writer = DataWriter::new(file.try_clone().unwrap(), &config).unwrap();
+
+        writer.write_all(b"first ").unwrap();
+        let checkpoint = writer.checkpoint().unwrap();
+        writer.write_all(b"rolled back ").unwrap();
+        writer.rollback(checkpoint).unwrap();
+
+        writer.set_compression(false).unwrap();
+        writer.write_all(b"stored ").unwrap();
+        let checkpoint = writer.checkpoint().unwrap();
+        writer.write_all(b"rolled back ").unwrap();
+        writer.rollback(checkpoint).unwrap();
+        writer.write_all(&stored_data).unwrap();
+
+        writer.set_compression(true).unwrap();
+        writer.write_all(b" last").unwrap();
+
+        let mut file = writer.finish().unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut decoder = zstd::stream::read::Decoder::new(file).unwrap();
+        decoder.window_log_max(31).unwrap();
+
+        let mut data = Vec::new();
+        decoder.read_to_end(&mut data).unwrap();
+
+        let mut expected = b"first stored ".to_vec();
+        expected.extend(&stored_data);
+        expected.extend(b" last");
+        assert!(data == expected);
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+use digest::Digest;
+use log::debug;
+
+use crate::core::GenericResult;
+use crate::storage::metadata::{Chunk, Fingerprint};
+use crate::util::chunker::{self, Chunker};
+use crate::util::file_reader::{FileReader, EMPTY_FILE_HASH};
+use crate::util::hash::Hash;
+use crate::util::logging::ThreadContext;
+use crate::util::sparse::{self, SparseDataReader, SparseFileReader};
+
+// Changed files which aren't bigger than this size are read into memory by worker threads, bigger
+// files are streamed by worker threads to the archive writer. Either way the file is read only once.
+// Test files are small, so the limit is lowered to test both reading paths.
+#[cfg(test)] const MAX_BUFFERED_FILE_SIZE: u64 = 16;
+#[cfg(not(test))] const MAX_BUFFERED_FILE_SIZE: u64 = 8 * 1024 * 1024;
+
+// Limits memory consumed by files which are read in advance
+const MAX_BUFFERED_SIZE: u64 = 128 * 1024 * 1024;
+
+// Limits memory consumed by a streamed file: the stream consists of data blocks or chunks
+const STREAM_BLOCK_SIZE: u64 = 1024 * 1024;
+const MAX_PENDING_STREAM_BLOCKS: usize = 4;
+
+// Files state in the last backup of the group
+pub type LastState = HashMap<PathBuf, FileState>;
+
+pub struct FileState {
+    pub fingerprint: Fingerprint,
+    pub hash: Hash,
+    pub chunks: Option<Vec<Chunk>>,
+}
+
+pub enum FileData {
+    // The file hasn't been changed since the last backup (chunks are set if it's stored by chunks) or
+    // has zero size
+    Unchanged {hash: Hash, size: u64, chunks: Option<Vec<Chunk>>},
+
+    // The file has been read and hashed by a worker thread
+    Buffered {hash: Hash, size: u64, buffer: FileBuffer},
+
+    // The file should be streamed to the archive writer (see FileStream)
+    Unread,
+}
+
+// Hashes files for deduplication in worker threads
+pub struct FileHasher {
+    last_state: Option<LastState>,
+    free_buffer_space: Mutex<u64>,
+    buffer_released: Condvar,
+    // Device and inode numbers of the files with multiple hard links
+    hard_links: Mutex<HashSet<(u64, u64)>>,
+}
+
+impl FileHasher {
+    pub fn new(last_state: Option<LastState>) -> FileHasher {
+        FileHasher {
+            last_state,
+            free_buffer_space: Mutex::new(MAX_BUFFERED_SIZE),
+            buffer_released: Condvar::new(),
+            hard_links: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // Checks whether the file is a hard link to a previously walked file, so it doesn't have to be read.
+    // Must be called in the archive order.
+    pub fn is_known_hard_link(&self, metadata: &fs::Metadata) -> bool {
+        metadata.nlink() > 1 && !self.hard_links.lock().unwrap().insert((metadata.dev(), metadata.ino()))
+    }
+
+    // Reserves buffer space for the file if it's going to be buffered. Must be called in the archive order:
+    // it waits for the space which is released by the archive writer when it writes the previous files.
+    pub fn reserve(self: &Arc<Self>, path: &Path, metadata: &fs::Metadata) -> Option<BufferReservation> {
+        let size = metadata.len();
+
+        if size == 0 || size > MAX_BUFFERED_FILE_SIZE || sparse::is_sparse(metadata) ||
+            self.get_unchanged_state(path, metadata).is_some() {
+            return None;
+        }
+
+        let mut free_space = self.free_buffer_space.lock().unwrap();
+        while *free_space < size {
+            free_space = self.buffer_released.wait(free_space).unwrap();
+        }
+        *free_space -= size;
+
+        Some(BufferReservation {size, hasher: self.clone()})
+    }
+
+    pub fn hash(
+        &self, path: &Path, file: &mut File, metadata: &fs::Metadata, reservation: Option<BufferReservation>,
+    ) -> GenericResult<FileData> {
+        let size = metadata.len();
+
+        if size == 0 {
+            debug!("{:?} has zero size.", path);
+            return Ok(FileData::Unchanged {hash: EMPTY_FILE_HASH.clone(), size, chunks: None});
+        }
+
+        if let Some(last_state) = self.get_unchanged_state(path, metadata) {
+            debug!("{:?} hasn't been changed.", path);
+            return Ok(FileData::Unchanged {
+                hash: last_state.hash.clone(), size, chunks: last_state.chunks.clone(),
+            });
+        }
+
+        // The file might have been changed since the space reservation. Sparse files are streamed by
+        // readers which are able to skip their holes.
+        let reservation = match reservation {
+            Some(reservation) if size <= reservation.size && !sparse::is_sparse(metadata) => reservation,
+            _ => return Ok(FileData::Unread),
+        };
+
+        let mut buffer = FileBuffer {
+            data: Vec::with_capacity(size as usize),
+            _reservation: reservation,
+        };
+
+        let mut file_reader = FileReader::new(file, size);
+        file_reader.read_to_end(&mut buffer.data)?;
+        let (bytes_read, hash) = file_reader.consume();
+
+        Ok(FileData::Buffered {hash, size: bytes_read, buffer})
+    }
+
+    fn get_unchanged_state(&self, path: &Path, metadata: &fs::Metadata) -> Option<&FileState> {
+        self.last_state.as_ref()
+            .and_then(|states| states.get(path))
+            .filter(|state| Fingerprint::new(metadata) == state.fingerprint)
+    }
+}
+
+pub struct BufferReservation {
+    size: u64,
+    hasher: Arc<FileHasher>,
+}
+
+impl Drop for BufferReservation {
+    fn drop(&mut self) {
+        *self.hasher.free_buffer_space.lock().unwrap() += self.size;
+        self.hasher.buffer_released.notify_all();
+    }
+}
+
+// File data (padded to the file size if it has been truncated during reading)
+pub struct FileBuffer {
+    data: Vec<u8>,
+    _reservation: BufferReservation,
+}
+
+impl FileBuffer {
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+// File data or chunks which are read and hashed by a worker thread while being consumed by the archive
+// writer
+pub struct FileStream {
+    blocks: Receiver<io::Result<Block>>,
+    block: Vec<u8>,
+    position: usize,
+    result: Option<(u64, Hash)>,
+}
+
+enum Block {
+    Data(Vec<u8>),
+    Chunk(Vec<u8>, Hash),
+    // The number of bytes read from the file and its hash (hash of the padded data for chunked files)
+    End(u64, Hash),
+}
+
+impl FileStream {
+    // Streams the file data (only the data regions for sparse files) or its chunks. The data is padded to
+    // the file size if the file is truncated during reading.
+    pub fn new(
+        mut file: File, regions: Option<sparse::Regions>, size: u64, chunk_size: Option<usize>,
+    ) -> FileStream {
+        FileStream::spawn(move |blocks| {
+            let mut sparse_file;
+            let reader: &mut dyn Read = match regions {
+                Some(ref regions) => {
+                    sparse_file = SparseFileReader::new(&mut file, regions, size);
+                    &mut sparse_file
+                },
+                None => &mut file,
+            };
+            let mut file_reader = FileReader::new(reader, size);
+
+            let chunks_hash = match (chunk_size, regions.as_ref()) {
+                (Some(chunk_size), _) => Some(send_chunks(&mut file_reader, chunk_size, blocks)?),
+                (None, Some(regions)) => {
+                    send_data(&mut SparseDataReader::new(&mut file_reader, regions), blocks)?;
+                    None
+                },
+                (None, None) => {
+                    send_data(&mut file_reader, blocks)?;
+                    None
+                },
+            };
+
+            let (bytes_read, hash) = file_reader.consume();
+            send(blocks, Block::End(bytes_read, chunks_hash.unwrap_or(hash)))
+        })
+    }
+
+    // Streams chunks of the buffered file
+    pub fn new_chunked(buffer: FileBuffer, chunk_size: usize) -> FileStream {
+        FileStream::spawn(move |blocks| {
+            let hash = send_chunks(&mut buffer.data(), chunk_size, blocks)?;
+            send(blocks, Block::End(buffer.data.len() as u64, hash))
+        })
+    }
+
+    fn spawn<F>(stream: F) -> FileStream
+        where F: FnOnce(&SyncSender<io::Result<Block>>) -> io::Result<()> + Send + 'static
+    {
+        let (sender, receiver) = mpsc::sync_channel(MAX_PENDING_STREAM_BLOCKS);
+        let context = ThreadContext::current();
+
+        rayon::spawn(move || {
+            let _context = ThreadContext::inherit(context);
+            if let Err(err) = stream(&sender) {
+                let _ = sender.send(Err(err));
+            }
+        });
+
+        FileStream {
+            blocks: receiver,
+            block: Vec::new(),
+            position: 0,
+            result: None,
+        }
+    }
+
+    pub fn next_chunk(&mut self) -> io::Result<Option<(Vec<u8>, Hash)>> {
+        match self.receive()? {
+            Some(Block::Chunk(data, hash)) => Ok(Some((data, hash))),
+            Some(_) => Err(io::Error::other("Got an unexpected file stream block")),
+            None => Ok(None),
+        }
+    }
+
+    // Returns the number of bytes read from the file and its hash. Must be called after the stream end.
+    pub fn finish(self) -> io::Result<(u64, Hash)> {
+        self.result.ok_or_else(|| io::Error::other("The file stream hasn't been consumed"))
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Block>> {
+        if self.result.is_some() {
+            return Ok(None);
+        }
+
+        match self.blocks.recv().map_err(|_| io::Error::other("File reading worker thread has crashed"))?? {
+            Block::End(bytes_read, hash) => {
+                self.result = Some((bytes_read, hash));
+                Ok(None)
+            },
+            block => Ok(Some(block)),
+        }
+    }
+}
+
+impl Read for FileStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() {
+            match self.receive()? {
+                Some(Block::Data(data)) => {
+                    self.block = data;
+                    self.position = 0;
+                },
+                Some(_) => return Err(io::Error::other("Got an unexpected file stream block")),
+                None => return Ok(0),
+            }
+        }
+
+        let size = buf.len().min(self.block.len() - self.position);
+        buf[..size].copy_from_slice(&self.block[self.position..self.position + size]);
+        self.position += size;
+
+        Ok(size)
+    }
+}
+
+fn send_data(reader: &mut dyn Read, blocks: &SyncSender<io::Result<Block>>) -> io::Result<()> {
+    loop {
+        let mut data = Vec::new();
+        (&mut *reader).take(STREAM_BLOCK_SIZE).read_to_end(&mut data)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        send(blocks, Block::Data(data))?;
+    }
+}
+
+fn send_chunks(
+    reader: &mut dyn Read, chunk_size: usize, blocks: &SyncSender<io::Result<Block>>,
+) -> io::Result<Hash> {
+    let mut chunker = Chunker::new(reader, chunk_size);
+    let mut digest = sha2::Sha512::new();
+
+    while let Some(data) = chunker.next_chunk()? {
+        digest.update(data);
+        send(blocks, Block::Chunk(data.to_vec(), chunker::get_chunk_hash(data)))?;
+    }
+
+    Ok(digest.finalize().as_slice().into())
+}
+
+fn send(blocks: &SyncSender<io::Result<Block>>, block: Block) -> io::Result<()> {
+    // The archive writer stops receiving the stream on error
+    blocks.send(Ok(block)).map_err(|_| io::Error::other("The file stream has been closed"))
+}
@@ -1,23 +1,138 @@
 mod backup;
 mod backuper;
 mod config;
+mod data_writer;
 mod filter;
+mod hasher;
+mod walker;
 
+use std::thread;
+
+use easy_logging::GlobalContext;
+use humansize::{self, SizeFormatter};
 use log::{info, error};
+use serde_derive::Serialize;
 
 use crate::config::BackupSpecConfig;
 use crate::core::GenericResult;
 use crate::providers::filesystem::Filesystem;
 use crate::storage::Storage;
+use crate::util::logging::ThreadContext;
 use crate::util::sys::acquire_lock;
 
-use self::backup::BackupInstance;
-use self::backuper::Backuper;
+use self::backup::{BackupInstance, BackupStats};
+use self::backuper::{Backuper, ItemResult};
 
-pub use self::config::{BackupConfig, BackupItemConfig};
+pub use self::config::{BackupConfig, BackupItemConfig, CompressionConfig};
+#[cfg(test)] pub use self::config::ChunkingConfig;
 pub use self::filter::PathFilter;
 
-pub fn backup(config: &BackupSpecConfig) -> GenericResult<bool> {
+#[derive(Serialize)]
+pub struct BackupResult {
+    pub name: String,
+    pub ok: bool,
+    pub dry_run: bool,
+    pub items: Vec<ItemResult>,
+}
+
+impl BackupResult {
+    pub fn print(&self) {
+        let format_size = |size| SizeFormatter::new(size, humansize::BINARY);
+
+        for (index, item) in self.items.iter().enumerate() {
+            if index != 0 {
+                println!();
+            }
+
+            let stats = &item.stats;
+            println!("{}:", item.path);
+            println!("  Files: {} ({} new, {} deduplicated)",
+                stats.files, format_size(stats.new_size), format_size(stats.deduplicated_size));
+            println!("  Directories: {}", stats.directories);
+            println!("  Symlinks: {}", stats.symlinks);
+            println!("  Hard links: {}", stats.hard_links);
+            println!("  Special files: {}", stats.special_files);
+
+            if !item.filtered_paths.is_empty() {
+                println!("  Filtered out:");
+                for path in &item.filtered_paths {
+                    println!("    {}", path.display());
+                }
+            }
+        }
+    }
+}
+
+pub fn print_results(results: &[BackupResult]) {
+    // Failed backups have no items and their errors are already logged
+    let results: Vec<_> = results.iter().filter(|result| !result.items.is_empty()).collect();
+
+    for (index, result) in results.iter().enumerate() {
+        if index != 0 {
+            println!();
+        }
+        if results.len() > 1 {
+            println!("[{}]", result.name);
+        }
+        result.print();
+    }
+}
+
+// Runs the specified backups. Backups on different storage roots don't contend for the storage lock,
+// so they can be run in parallel, but then they have to use thread logging context instead of the global
+// one.
+pub fn backup_many(configs: &[&BackupSpecConfig], dry_run: bool, parallel: bool) -> Vec<BackupResult> {
+    if !parallel || configs.len() < 2 {
+        return configs.iter().map(|config| {
+            let _context = (configs.len() > 1).then(|| GlobalContext::new(&config.name));
+            backup_or_log_error(config, dry_run)
+        }).collect();
+    }
+
+    let mut queues: Vec<Vec<(usize, &BackupSpecConfig)>> = Vec::new();
+
+    for (index, &config) in configs.iter().enumerate() {
+        match queues.iter_mut().find(|queue| queue[0].1.path == config.path) {
+            Some(queue) => queue.push((index, config)),
+            None => queues.push(vec![(index, config)]),
+        }
+    }
+
+    // Backups wait for file hashing jobs, so they mustn't be run in the worker pool
+    let mut results: Vec<(usize, BackupResult)> = thread::scope(|scope| {
+        let threads: Vec<_> = queues.iter().map(|queue| scope.spawn(move || {
+            queue.iter().map(|&(index, config)| {
+                let _context = ThreadContext::new(&config.name);
+                (index, backup_or_log_error(config, dry_run))
+            }).collect::<Vec<_>>()
+        })).collect();
+
+        threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect()
+    });
+
+    results.sort_by_key(|&(index, _)| index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn backup_or_log_error(config: &BackupSpecConfig, dry_run: bool) -> BackupResult {
+    backup(config, dry_run).unwrap_or_else(|err| {
+        error!("Backup failed: {}.", err);
+
+        BackupResult {
+            name: config.name.clone(),
+            ok: false,
+            dry_run,
+            items: Vec::new(),
+        }
+    })
+}
+
+pub fn backup(config: &BackupSpecConfig, dry_run: bool) -> GenericResult<BackupResult> {
+    if dry_run {
+        return backup_dry_run(config);
+    }
+
+    let name = config.name.clone();
     let _lock = acquire_lock(&config.path)?;
     let storage = Storage::new_read_write(Filesystem::new(), &config.path);
 
@@ -25,10 +140,23 @@ pub fn backup(config: &BackupSpecConfig) -> GenericResult<bool> {
         "Backup rules aren't configured for the specified backup")?;
 
     let (backup, mut ok) = BackupInstance::create(config, &storage)?;
-    ok &= Backuper::new(config, backup)?.run()?;
+    let (items, backup_ok) = Backuper::new(config, backup)?.run()?;
+    ok &= backup_ok;
 
     ok &= gc_groups(&storage, config.max_backup_groups)?;
-    Ok(ok)
+    Ok(BackupResult {name, ok, dry_run: false, items})
+}
+
+fn backup_dry_run(config: &BackupSpecConfig) -> GenericResult<BackupResult> {
+    let name = config.name.clone();
+    let storage = Storage::new_read_only(Filesystem::new(), &config.path);
+
+    let config = config.backup.as_ref().ok_or(
+        "Backup rules aren't configured for the specified backup")?;
+
+    let (backup, ok) = BackupInstance::dry_run(config, &storage)?;
+    let (items, backup_ok) = Backuper::new(config, backup)?.run()?;
+    Ok(BackupResult {name, ok: ok && backup_ok, dry_run: true, items})
 }
 
 fn gc_groups(storage: &Storage, max_groups: usize) -> GenericResult<bool> {
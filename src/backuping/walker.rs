@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::{OpenOptionsExt, FileTypeExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+use itertools::Itertools;
+use log::debug;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+
+use crate::core::{GenericError, GenericResult};
+use crate::util::{self, logging::ThreadContext, xattr::{self, Xattrs}};
+
+use super::BackupItemConfig;
+use super::hasher::{BufferReservation, FileData, FileHasher};
+
+// Backup entries in the archive order. Files are opened and hashed by the worker pool, so they are
+// represented by pending results which are received by the archive writer in the same order.
+pub enum Entry {
+    Directory(PathBuf, Metadata, Xattrs),
+    File(PreparedFile),
+    // Hard link to a previously walked file
+    HardLink(PathBuf, Metadata),
+    Symlink(PathBuf, Metadata, PathBuf, Xattrs),
+    // FIFO or device node
+    Special(PathBuf, Metadata, Xattrs),
+    Pending(Receiver<GenericResult<Entry>>),
+    Warning(String),
+    Error(String),
+}
+
+pub struct PreparedFile {
+    pub path: PathBuf,
+    pub metadata: Metadata,
+    pub xattrs: Xattrs,
+    pub file: File,
+    pub data: FileData,
+}
+
+// The archive writer has stopped receiving the entries
+pub struct Interrupted;
+
+type WalkResult<T = ()> = Result<T, Interrupted>;
+
+pub struct Walker {
+    entries: SyncSender<Entry>,
+    hasher: Arc<FileHasher>,
+    root_parents: HashSet<PathBuf>,
+    filtered_paths: Vec<PathBuf>,
+}
+
+impl Walker {
+    // Limits the number of files which are prepared in advance (and kept open)
+    pub const MAX_PENDING_ENTRIES: usize = 128;
+
+    pub fn new(
+        entries: SyncSender<Entry>, hasher: Arc<FileHasher>, root_parents: HashSet<PathBuf>,
+    ) -> Walker {
+        Walker {entries, hasher, root_parents, filtered_paths: Vec::new()}
+    }
+
+    // Returns the updated set of already backed up root parent directories and the filtered out paths
+    pub fn walk(mut self, path: &Path, item: &BackupItemConfig) -> (HashSet<PathBuf>, Vec<PathBuf>) {
+        let _ = self.walk_path(path, Path::new(""), None, item);
+        (self.root_parents, self.filtered_paths)
+    }
+
+    // Parent device is None for the top level path
+    fn walk_path(
+        &mut self, path: &Path, relative_path: &Path, parent_device: Option<u64>, item: &BackupItemConfig,
+    ) -> WalkResult {
+        debug!("Backing up {:?}...", path);
+        let top_level = parent_device.is_none();
+
+        if let Err(err) = crate::storage::metadata::validate_path(path) {
+            return self.send(path_error(path, err));
+        }
+
+        if top_level && !self.walk_parent_directories(path)? {
+            return Ok(());
+        }
+
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                return self.send(access_error(path, top_level, err, None));
+            },
+        };
+
+        let file_type = metadata.file_type();
+
+        if file_type.is_file() {
+            self.walk_file(path, top_level, metadata)
+        } else if file_type.is_dir() {
+            if let Some(device) = parent_device && metadata.dev() != device && !self.should_cross_filesystem(path, item)? {
+                debug!("Skipping {:?} mount point contents.", path);
+                return self.walk_mount_point(path, metadata);
+            }
+            self.walk_directory(path, relative_path, top_level, item, metadata)
+        } else if file_type.is_symlink() {
+            self.walk_symlink(path, top_level, metadata)
+        } else if file_type.is_block_device() || file_type.is_char_device() || file_type.is_fifo() {
+            self.walk_special(path, top_level, metadata)
+        } else if !top_level && file_type.is_socket() {
+            // Sockets can't be stored in tar archives and have no sense without the process which
+            // listens on them
+            self.send(Entry::Warning(format!("Skipping {:?}: sockets aren't supported", path)))
+        } else {
+            self.send(path_error(path, "unsupported file type"))
+        }
+    }
+
+    fn walk_parent_directories(&mut self, path: &Path) -> WalkResult<bool> {
+        let mut parent = PathBuf::new();
+
+        for (index, part) in path.components().dropping_back(1).enumerate() {
+            parent.push(part);
+
+            match part {
+                Component::RootDir if index == 0 => {
+                    continue;
+                },
+                Component::Normal(_) if index != 0 => {
+                },
+                _ => {
+                    self.send(path_error(path, "invalid path"))?;
+                    return Ok(false);
+                },
+            }
+
+            if self.root_parents.contains(&parent) {
+                continue
+            }
+
+            let metadata = match fs::symlink_metadata(&parent) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    self.send(path_error(path, err))?;
+                    return Ok(false);
+                },
+            };
+
+            if !metadata.is_dir() {
+                self.send(path_error(path, format!(
+                    "{:?} has changed its type during the backup", parent)))?;
+                return Ok(false);
+            }
+
+            let xattrs = match xattr::read(&parent) {
+                Ok(xattrs) => xattrs,
+                Err(err) => {
+                    self.send(path_error(path, format!("{:?}: {}", parent, err)))?;
+                    return Ok(false);
+                },
+            };
+
+            self.send(Entry::Directory(parent.clone(), metadata, xattrs))?;
+            self.root_parents.insert(parent.clone());
+        }
+
+        Ok(true)
+    }
+
+    fn walk_directory(
+        &mut self, path: &Path, relative_path: &Path, top_level: bool, item: &BackupItemConfig,
+        metadata: Metadata,
+    ) -> WalkResult {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                return self.send(access_error(path, top_level, err, Some(Errno::ENOTDIR)));
+            },
+        };
+
+        let xattrs = match xattr::read(path) {
+            Ok(xattrs) => xattrs,
+            Err(err) => {
+                return self.send(access_error(path, top_level, err, None));
+            },
+        };
+
+        let mut names = Vec::new();
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    return self.send(access_error(path, top_level, err, None));
+                },
+            };
+            names.push(entry.file_name());
+        }
+
+        let device = metadata.dev();
+        if !top_level || !util::sys::is_root_path(path) {
+            self.send(Entry::Directory(path.to_owned(), metadata, xattrs))?;
+        }
+
+        // To make tests predictable
+        if cfg!(test) {
+            names.sort();
+        }
+
+        for name in names {
+            let entry_path = path.join(&name);
+            let entry_relative_path = relative_path.join(&name);
+
+            match item.filter.check(&entry_relative_path) {
+                Ok(allow) => if allow {
+                    self.walk_path(&entry_path, &entry_relative_path, Some(device), item)?;
+                } else {
+                    debug!("Filtering out {:?}.", entry_path);
+                    self.filtered_paths.push(entry_path);
+                },
+                Err(err) => {
+                    self.send(path_error(&entry_path, err))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Checks whether the mount point contents should be backed up
+    fn should_cross_filesystem(&mut self, path: &Path, item: &BackupItemConfig) -> WalkResult<bool> {
+        if item.one_file_system {
+            return Ok(false);
+        } else if item.filesystems.is_empty() {
+            return Ok(true);
+        }
+
+        match util::sys::get_filesystem_type(path) {
+            Ok(filesystem_type) => Ok(item.filesystems.contains(&filesystem_type)),
+            Err(err) => {
+                self.send(path_error(path, err))?;
+                Ok(false)
+            },
+        }
+    }
+
+    // Backs up the mount point directory itself without its contents
+    fn walk_mount_point(&mut self, path: &Path, metadata: Metadata) -> WalkResult {
+        let entry = match xattr::read(path) {
+            Ok(xattrs) => Entry::Directory(path.to_owned(), metadata, xattrs),
+            Err(err) => access_error(path, false, err, None),
+        };
+        self.send(entry)
+    }
+
+    fn walk_file(&mut self, path: &Path, top_level: bool, metadata: Metadata) -> WalkResult {
+        // Top level paths are read anyway to report their errors properly
+        if self.hasher.is_known_hard_link(&metadata) && !top_level {
+            return self.send(Entry::HardLink(path.to_owned(), metadata));
+        }
+
+        // Buffer space is reserved in the archive order, so the file hashing jobs never wait for it
+        let reservation = self.hasher.reserve(path, &metadata);
+
+        let (sender, receiver) = mpsc::channel();
+        let path = path.to_owned();
+        let hasher = self.hasher.clone();
+        let context = ThreadContext::current();
+
+        rayon::spawn(move || {
+            let _context = ThreadContext::inherit(context);
+            let _ = sender.send(prepare_file(path, top_level, &hasher, reservation));
+        });
+
+        self.send(Entry::Pending(receiver))
+    }
+
+    fn walk_symlink(&mut self, path: &Path, top_level: bool, metadata: Metadata) -> WalkResult {
+        let entry = match fs::read_link(path) {
+            Ok(target) => match xattr::read(path) {
+                Ok(xattrs) => Entry::Symlink(path.to_owned(), metadata, target, xattrs),
+                Err(err) => access_error(path, top_level, err, None),
+            },
+            Err(err) => access_error(path, top_level, err, Some(Errno::EINVAL)),
+        };
+        self.send(entry)
+    }
+
+    fn walk_special(&mut self, path: &Path, top_level: bool, metadata: Metadata) -> WalkResult {
+        let entry = match xattr::read(path) {
+            Ok(xattrs) => Entry::Special(path.to_owned(), metadata, xattrs),
+            Err(err) => access_error(path, top_level, err, None),
+        };
+        self.send(entry)
+    }
+
+    fn send(&self, entry: Entry) -> WalkResult {
+        self.entries.send(entry).map_err(|_| Interrupted)
+    }
+}
+
+pub fn prepare_file(
+    path: PathBuf, top_level: bool, hasher: &FileHasher, reservation: Option<BufferReservation>,
+) -> GenericResult<Entry> {
+    let mut open_options = OpenOptions::new();
+    open_options.read(true).custom_flags(OFlag::O_NOFOLLOW.bits());
+
+    let mut file = match open_options.open(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Ok(access_error(&path, top_level, err, Some(Errno::ELOOP)));
+        },
+    };
+
+    let metadata = match file.metadata() {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            return Ok(access_error(&path, top_level, err, None));
+        },
+    };
+
+    if !metadata.is_file() {
+        return Ok(type_change_error(&path, top_level));
+    }
+
+    let xattrs = match xattr::read(&path) {
+        Ok(xattrs) => xattrs,
+        Err(err) => {
+            return Ok(access_error(&path, top_level, err, None));
+        },
+    };
+
+    let data = hasher.hash(&path, &mut file, &metadata, reservation).map_err(|e| format!(
+        "Failed to backup {:?}: {}", path, e))?;
+
+    Ok(Entry::File(PreparedFile {path, metadata, xattrs, file, data}))
+}
+
+fn access_error(path: &Path, top_level: bool, err: io::Error, type_change_errno: Option<Errno>) -> Entry {
+    if let (Some(type_change_errno), Some(errno)) = (type_change_errno, err.raw_os_error()) {
+        if Errno::from_raw(errno) == type_change_errno {
+            return type_change_error(path, top_level);
+        }
+    }
+
+    if err.kind() == ErrorKind::NotFound && !top_level {
+        return Entry::Warning(format!("Failed to backup {:?}: it was deleted during backing up", path));
+    }
+
+    path_error(path, err)
+}
+
+fn type_change_error(path: &Path, top_level: bool) -> Entry {
+    let message = format!("Skipping {:?}: it changed its type during backing up", path);
+    if top_level {
+        Entry::Error(message)
+    } else {
+        Entry::Warning(message)
+    }
+}
+
+fn path_error<E: Into<GenericError>>(path: &Path, err: E) -> Entry {
+    Entry::Error(format!("Failed to backup {:?}: {}", path, err.into()))
+}
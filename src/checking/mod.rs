@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use easy_logging::GlobalContext;
+use log::{info, error};
+use nix::unistd::{self, AccessFlags};
+
+use crate::config::{Config, BackupConfig, BackupSpecConfig, UploadConfig};
+use crate::core::{EmptyResult, GenericResult};
+use crate::uploading;
+
+// Checks the configuration against the live system: unlike configuration loading which validates
+// only its syntax, reports all found problems at once.
+pub fn check_config(config: &Config) -> GenericResult<bool> {
+    let mut checker = Checker {ok: true, gpg_checked: false};
+
+    for backup in &config.backups {
+        info!("Checking {:?} backup configuration...", backup.name);
+
+        let _context = GlobalContext::new(&backup.name);
+        checker.check_backup(backup);
+    }
+
+    if checker.ok {
+        info!("The configuration is valid.");
+    }
+
+    Ok(checker.ok)
+}
+
+struct Checker {
+    ok: bool,
+    gpg_checked: bool,
+}
+
+impl Checker {
+    fn check_backup(&mut self, config: &BackupSpecConfig) {
+        let result = check_storage(Path::new(&config.path));
+        self.handle_result(result);
+
+        if let Some(backup) = config.backup.as_ref() {
+            self.check_items(backup);
+        }
+
+        if let Some(upload) = config.upload.as_ref() {
+            self.check_upload(upload);
+        }
+    }
+
+    fn check_items(&mut self, config: &BackupConfig) {
+        let mut roots: Vec<(&str, PathBuf)> = Vec::new();
+
+        for item in &config.items {
+            match item.path() {
+                Ok(path) => {
+                    for (other_item, other_path) in &roots {
+                        if path.starts_with(other_path) || other_path.starts_with(&path) {
+                            self.handle_error(format_args!(
+                                "{:?} backup item intersects with {:?}", item.path, other_item));
+                        }
+                    }
+                    roots.push((&item.path, path));
+                },
+                Err(err) => self.handle_error(format_args!(
+                    "Invalid {:?} backup item: {}", item.path, err)),
+            }
+
+            for (name, command) in [("before", &item.before), ("after", &item.after)] {
+                if let Some(command) = command && let Err(err) = check_command(command) {
+                    self.handle_error(format_args!(
+                        "Invalid `{}` command for {:?}: {}", name, item.path, err));
+                }
+            }
+        }
+    }
+
+    fn check_upload(&mut self, config: &UploadConfig) {
+        if !self.gpg_checked {
+            let result = check_gpg();
+            self.handle_result(result);
+            self.gpg_checked = true;
+        }
+
+        let result = uploading::get_cloud_storage(config).and_then(|storage| {
+            info!("Checking {} credentials...", storage.name());
+            storage.provider.upload()?.check_credentials().map_err(|e| format!(
+                "{} credentials check failed: {}", storage.name(), e).into())
+        });
+        self.handle_result(result);
+    }
+
+    fn handle_result(&mut self, result: EmptyResult) {
+        if let Err(err) = result {
+            self.handle_error(format_args!("{}", err));
+        }
+    }
+
+    fn handle_error(&mut self, message: std::fmt::Arguments) {
+        error!("{}.", message);
+        self.ok = false;
+    }
+}
+
+fn check_storage(path: &Path) -> EmptyResult {
+    let metadata = fs::metadata(path).map_err(|e| format!(
+        "Invalid backup storage path {:?}: {}", path, e))?;
+
+    if !metadata.is_dir() {
+        return Err!("Invalid backup storage path {:?}: it's not a directory", path);
+    }
+
+    unistd::access(path, AccessFlags::W_OK | AccessFlags::X_OK).map_err(|e| format!(
+        "Backup storage {:?} is not writable: {}", path, e))?;
+
+    Ok(())
+}
+
+fn check_command(command: &str) -> EmptyResult {
+    let output = Command::new("bash").arg("-n").arg("-c").arg(command)
+        .stdin(Stdio::null()).output()
+        .map_err(|e| format!("Failed to execute bash: {}", e))?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        let error = error.trim_end();
+        if error.is_empty() {
+            return Err!("bash has terminated with an error exit code");
+        }
+        return Err!("{}", error);
+    }
+
+    Ok(())
+}
+
+fn check_gpg() -> EmptyResult {
+    let status = Command::new("gpg").arg("--version")
+        .stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null())
+        .status().map_err(|e| format!("Unable to execute gpg which is required for upload: {}", e))?;
+
+    if !status.success() {
+        return Err!("gpg which is required for upload doesn't work: it has terminated with an error exit code");
+    }
+
+    Ok(())
+}
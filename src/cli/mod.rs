@@ -2,19 +2,84 @@ mod parser;
 
 use std::path::PathBuf;
 
+use crate::authorizing::OauthProvider;
+
 pub use parser::{Parser, GlobalOptions};
 
 pub enum Action {
+    Auth {
+        provider: OauthProvider,
+        client_id: String,
+        client_secret: String,
+        port: u16,
+        token_url: Option<String>,
+        output_path: Option<PathBuf>,
+    },
+
     Backup {
+        names: Vec<String>, // empty means all backups
+        dry_run: bool,
+        parallel: bool,
+    },
+
+    Cat {
+        backup_path: PathBuf,
+        path: PathBuf,
+    },
+
+    CheckConfig,
+
+    Diff {
+        backup_path: PathBuf,
+        other_backup_path: PathBuf,
+    },
+
+    Export {
+        backup_path: PathBuf,
+        output_path: Option<PathBuf>,
+        compress: bool,
+    },
+
+    Import {
         name: String,
+        paths: Vec<PathBuf>,
+    },
+
+    List {
+        name: String,
+        cloud: bool,
+    },
+
+    ListFiles {
+        backup_path: PathBuf,
+        path: PathBuf,
+        recursive: bool,
+        long: bool,
     },
 
     Restore {
         backup_path: PathBuf,
         restore_path: PathBuf,
+        paths: Vec<PathBuf>,
+        filter: Option<String>,
+        cloud: Option<String>,
+    },
+
+    Status {
+        name: Option<String>,
+        cloud: bool,
     },
 
     Upload {
         verify: bool,
     },
+
+    Verify {
+        name: String,
+    },
+
+    Versions {
+        name: String,
+        path: String,
+    },
 }
\ No newline at end of file
@@ -2,8 +2,11 @@ use std::path::PathBuf;
 
 use clap::{Command, Arg, ArgAction, ArgMatches, value_parser};
 use const_format::formatcp;
+use itertools::Itertools;
 
+use crate::authorizing::OauthProvider;
 use crate::core::GenericResult;
+use crate::util::output::OutputFormat;
 
 use super::Action;
 
@@ -14,6 +17,7 @@ pub struct Parser {
 pub struct GlobalOptions {
     pub log_level: log::Level,
     pub config_path: PathBuf,
+    pub format: OutputFormat,
 }
 
 impl Parser {
@@ -44,28 +48,169 @@ impl Parser {
                 .action(ArgAction::SetTrue)
                 .help("Show only warning and error messages (intended to be used from cron)"))
 
+            .arg(Arg::new("format").long("format")
+                .value_name("FORMAT")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .help("Output format"))
+
             .arg(Arg::new("verbose")
                 .short('v').long("verbose")
                 .conflicts_with("cron")
                 .action(ArgAction::Count)
                 .help("Set verbosity level"))
 
+            .subcommand(Command::new("auth")
+                .about("Obtain OAuth credentials for the specified cloud provider")
+                .arg(Arg::new("PROVIDER")
+                    .value_parser(["dropbox", "google-drive", "yandex-disk"])
+                    .help("Cloud provider name")
+                    .required(true))
+                .arg(Arg::new("client_id").long("client-id")
+                    .value_name("ID")
+                    .help("OAuth client ID")
+                    .required(true))
+                .arg(Arg::new("client_secret").long("client-secret")
+                    .value_name("SECRET")
+                    .help("OAuth client secret")
+                    .required(true))
+                .arg(Arg::new("port").short('p').long("port")
+                    .value_name("PORT")
+                    .value_parser(value_parser!(u16))
+                    .help("Port to listen on for the redirect (must match the redirect URI registered for the application) [default: random]"))
+                .arg(Arg::new("token_endpoint").long("token-endpoint")
+                    .value_name("URL")
+                    .help("Override the provider's token endpoint URL"))
+                .arg(Arg::new("output").short('o').long("output")
+                    .value_name("FILE")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("File to write the provider configuration to or \"-\" for stdout [default: -]")))
+
             .subcommand(Command::new("backup")
-                .about("Run backup process for the specified backup name")
+                .about("Run backup process for the specified backup names")
                 .arg(Arg::new("NAME")
+                    .action(ArgAction::Append)
                     .help("Backup name")
+                    .required_unless_present("all"))
+                .arg(Arg::new("all").long("all")
+                    .action(ArgAction::SetTrue)
+                    .conflicts_with("NAME")
+                    .help("Run all backups which have backup rules configured"))
+                .arg(Arg::new("parallel").short('p').long("parallel")
+                    .action(ArgAction::SetTrue)
+                    .help("Run backups stored in different storages in parallel"))
+                .arg(Arg::new("dry_run").long("dry-run")
+                    .action(ArgAction::SetTrue)
+                    .help("Show what would be backed up without creating the backup")))
+
+            .subcommand(Command::new("cat")
+                .about("Write contents of the specified file from the backup to stdout")
+                .arg(Arg::new("BACKUP_PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Backup path")
+                    .required(true))
+                .arg(Arg::new("PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("File path inside of the backup")
                     .required(true)))
 
+            .subcommand(Command::new("config")
+                .about("Configuration management")
+                .subcommand_required(true)
+                .subcommand(Command::new("check")
+                    .about("Check the configuration against the system: backup paths, commands, cloud credentials")))
+
+            .subcommand(Command::new("diff")
+                .about("Show changes between two backups")
+                .arg(Arg::new("BACKUP_PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Old backup path")
+                    .required(true))
+                .arg(Arg::new("OTHER_BACKUP_PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("New backup path")
+                    .required(true)))
+
+            .subcommand(Command::new("export")
+                .about("Export the backup as a self-contained tar archive")
+                .arg(Arg::new("BACKUP_PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Backup path")
+                    .required(true))
+                .arg(Arg::new("output").short('o').long("output")
+                    .value_name("FILE")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Archive path or \"-\" for stdout [default: -]"))
+                .arg(Arg::new("zstd").short('z').long("zstd")
+                    .action(ArgAction::SetTrue)
+                    .help("Compress the archive with zstd")))
+
+            .subcommand(Command::new("import")
+                .about("Import backups downloaded from cloud to the local storage")
+                .arg(Arg::new("NAME")
+                    .help("Backup name")
+                    .required(true))
+                .arg(Arg::new("PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .action(ArgAction::Append)
+                    .help("Backup archive or backup group directory path")
+                    .required(true)))
+
+            .subcommand(Command::new("list")
+                .about("List backup groups and backups with their statistics")
+                .arg(Arg::new("NAME")
+                    .help("Backup name")
+                    .required(true))
+                .arg(Arg::new("cloud").long("cloud")
+                    .action(ArgAction::SetTrue)
+                    .help("List backups in the cloud storage too")))
+
+            .subcommand(Command::new("ls")
+                .about("List files in the specified backup")
+                .arg(Arg::new("BACKUP_PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Backup path")
+                    .required(true))
+                .arg(Arg::new("PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Path inside of the backup to list [default: /]"))
+                .arg(Arg::new("recursive").short('r').long("recursive")
+                    .action(ArgAction::SetTrue)
+                    .help("List files recursively"))
+                .arg(Arg::new("long").short('l').long("long")
+                    .action(ArgAction::SetTrue)
+                    .help("Read data archive to show directories, symlinks and file attributes")))
+
             .subcommand(Command::new("restore")
                 .about("Restore the specified backup")
                 .arg(Arg::new("BACKUP_PATH")
                     .value_parser(value_parser!(PathBuf))
-                    .help("Backup path")
+                    .help("Backup path (or backup name when restoring from cloud)")
                     .required(true))
                 .arg(Arg::new("RESTORE_PATH")
                     .value_parser(value_parser!(PathBuf))
                     .help("Path to restore the backup to")
-                    .required(true)))
+                    .required(true))
+                .arg(Arg::new("PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .action(ArgAction::Append)
+                    .help("Restore only the specified paths"))
+                .arg(Arg::new("filter").short('f').long("filter")
+                    .value_name("RULE")
+                    .allow_hyphen_values(true)
+                    .action(ArgAction::Append)
+                    .help("Filter rule (\"+ GLOB\" or \"- GLOB\") matched against absolute paths"))
+                .arg(Arg::new("cloud").long("cloud")
+                    .value_name("NAME")
+                    .help("Download the backup from cloud storage of the specified backup configuration (requires free disk space for the downloaded backups of its group)")))
+
+            .subcommand(Command::new("status")
+                .about("Check backups state (exits with Nagios plugin return codes)")
+                .arg(Arg::new("NAME")
+                    .help("Backup name [default: all backups]"))
+                .arg(Arg::new("cloud").long("cloud")
+                    .action(ArgAction::SetTrue)
+                    .help("Check backups in the cloud storage too")))
 
             .subcommand(Command::new("upload")
                 .about("Upload backups to cloud")
@@ -73,9 +218,24 @@ impl Parser {
                     .action(ArgAction::SetTrue)
                     .help("Skip backup verification before uploading")))
 
+            .subcommand(Command::new("verify")
+                .about("Verify backups data by reading and checking the hash of every file")
+                .arg(Arg::new("NAME")
+                    .help("Backup name")
+                    .required(true)))
+
+            .subcommand(Command::new("versions")
+                .about("Show all versions of the specified file stored in backups")
+                .arg(Arg::new("NAME")
+                    .help("Backup name")
+                    .required(true))
+                .arg(Arg::new("PATH")
+                    .help("Absolute file path or glob")
+                    .required(true)))
+
             .get_matches();
 
-        let log_level = match matches.get_count("verbose") {
+        let mut log_level = match matches.get_count("verbose") {
             0 => if matches.get_flag("cron") {
                 log::Level::Warn
             } else {
@@ -86,32 +246,125 @@ impl Parser {
             _ => return Err!("Invalid verbosity level"),
         };
 
+        let format = match matches.get_one::<String>("format").unwrap().as_str() {
+            "json" => OutputFormat::Json,
+            _ => OutputFormat::Text,
+        };
+
+        if format.is_json() && let Some(command) = matches.subcommand_name() &&
+            !["backup", "list", "restore", "status"].contains(&command)
+        {
+            return Err!("JSON output format is not supported by {} command", command);
+        }
+
+        // Info and debug messages are written to stdout, so suppress them for commands which output
+        // data to stdout. JSON output doesn't need it, because logging is redirected to stderr then.
+        let stdout_output = match matches.subcommand() {
+            Some(("cat", _)) => true,
+            Some(("status", _)) => !format.is_json(),
+            Some(("auth" | "export", matches)) => get_output_path(matches).is_none(),
+            _ => false,
+        };
+        if stdout_output {
+            log_level = std::cmp::min(log_level, log::Level::Warn);
+        }
+
         let config_path = matches.get_one("config").cloned().unwrap_or_else(||
             PathBuf::from(shellexpand::tilde(DEFAULT_CONFIG_PATH).to_string()));
 
         self.matches.replace(matches);
 
-        Ok(GlobalOptions {log_level, config_path})
+        Ok(GlobalOptions {log_level, config_path, format})
     }
 
     pub fn parse(self) -> GenericResult<Action> {
         let (command, matches) = self.matches.as_ref().unwrap().subcommand().unwrap();
 
         Ok(match command {
+            "auth" => Action::Auth {
+                provider: OauthProvider::from_name(matches.get_one::<String>("PROVIDER").unwrap()).unwrap(),
+                client_id: matches.get_one("client_id").cloned().unwrap(),
+                client_secret: matches.get_one("client_secret").cloned().unwrap(),
+                port: matches.get_one("port").cloned().unwrap_or(0),
+                token_url: matches.get_one("token_endpoint").cloned(),
+                output_path: get_output_path(matches),
+            },
+
             "backup" => Action::Backup {
+                names: matches.get_many("NAME").map(|names| names.cloned().unique().collect()).unwrap_or_default(),
+                dry_run: matches.get_flag("dry_run"),
+                parallel: matches.get_flag("parallel"),
+            },
+
+            "cat" => Action::Cat {
+                backup_path: matches.get_one("BACKUP_PATH").cloned().unwrap(),
+                path: matches.get_one("PATH").cloned().unwrap(),
+            },
+
+            "config" => match matches.subcommand_name().unwrap() {
+                "check" => Action::CheckConfig,
+                _ => unreachable!(),
+            },
+
+            "diff" => Action::Diff {
+                backup_path: matches.get_one("BACKUP_PATH").cloned().unwrap(),
+                other_backup_path: matches.get_one("OTHER_BACKUP_PATH").cloned().unwrap(),
+            },
+
+            "export" => Action::Export {
+                backup_path: matches.get_one("BACKUP_PATH").cloned().unwrap(),
+                output_path: get_output_path(matches),
+                compress: matches.get_flag("zstd"),
+            },
+
+            "import" => Action::Import {
+                name: matches.get_one("NAME").cloned().unwrap(),
+                paths: matches.get_many("PATH").unwrap().cloned().collect(),
+            },
+
+            "list" => Action::List {
                 name: matches.get_one("NAME").cloned().unwrap(),
+                cloud: matches.get_flag("cloud"),
+            },
+
+            "ls" => Action::ListFiles {
+                backup_path: matches.get_one("BACKUP_PATH").cloned().unwrap(),
+                path: matches.get_one("PATH").cloned().unwrap_or_else(|| PathBuf::from("/")),
+                recursive: matches.get_flag("recursive"),
+                long: matches.get_flag("long"),
             },
 
             "restore" => Action::Restore {
                 backup_path: matches.get_one("BACKUP_PATH").cloned().unwrap(),
                 restore_path: matches.get_one("RESTORE_PATH").cloned().unwrap(),
+                paths: matches.get_many("PATH").unwrap_or_default().cloned().collect(),
+                filter: matches.get_many::<String>("filter").map(|mut rules| rules.join("\n")),
+                cloud: matches.get_one("cloud").cloned(),
+            },
+
+            "status" => Action::Status {
+                name: matches.get_one("NAME").cloned(),
+                cloud: matches.get_flag("cloud"),
             },
 
             "upload" => Action::Upload {
                 verify: !matches.get_flag("skip_verify"),
             },
 
+            "verify" => Action::Verify {
+                name: matches.get_one("NAME").cloned().unwrap(),
+            },
+
+            "versions" => Action::Versions {
+                name: matches.get_one("NAME").cloned().unwrap(),
+                path: matches.get_one("PATH").cloned().unwrap(),
+            },
+
             _ => unreachable!(),
         })
     }
-}
\ No newline at end of file
+}
+
+fn get_output_path(matches: &ArgMatches) -> Option<PathBuf> {
+    matches.get_one::<PathBuf>("output").filter(|path| path.as_os_str() != "-").cloned()
+}
@@ -9,7 +9,7 @@ use validator::Validate;
 use crate::core::GenericResult;
 
 pub use crate::backuping::BackupConfig;
-#[cfg(test)] pub use crate::backuping::BackupItemConfig;
+#[cfg(test)] pub use crate::backuping::{BackupItemConfig, ChunkingConfig, CompressionConfig};
 pub use crate::uploading::UploadConfig;
 
 #[derive(Deserialize, Validate)]
@@ -54,6 +54,7 @@ impl Config {
             }
 
             backup.path = validate_local_path(&backup.path)?;
+
             if let Some(upload) = backup.upload.as_mut() {
                 upload.path = validate_path(&upload.path)?;
             }
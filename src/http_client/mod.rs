@@ -6,10 +6,10 @@ mod response;
 
 use std::error::Error;
 use std::fmt;
-use std::time::Duration;
+use std::io::Read;
 
 use log::{log_enabled, trace};
-use reqwest::blocking::Client;
+use reqwest::blocking::{Client, Response};
 
 use crate::core::GenericResult;
 
@@ -41,6 +41,30 @@ impl HttpClient {
     }
 
     pub fn send<R, E>(&self, mut request: HttpRequest<R, E>) -> Result<R, HttpClientError<E>> {
+        let response = self.send_request(&mut request)?;
+        let response = read_response(response)?;
+
+        if response.status.is_success() {
+            Ok(request.reply_reader.read(response)?)
+        } else {
+            Err(read_error(&request, response))
+        }
+    }
+
+    // Sends the request and returns a reader for the response body without reading it into memory
+    pub fn download<E>(&self, mut request: HttpRequest<HttpResponse, E>) -> Result<Box<dyn Read + Send>, HttpClientError<E>> {
+        let response = self.send_request(&mut request)?;
+
+        let status = response.status();
+        if status.is_success() {
+            trace!("Got {} response. Streaming its body...", status);
+            return Ok(Box::new(response));
+        }
+
+        Err(read_error(&request, read_response(response)?))
+    }
+
+    fn send_request<R, E>(&self, request: &mut HttpRequest<R, E>) -> GenericResult<Response> {
         let mut headers = self.default_headers.clone();
         for (name, value) in request.headers.drain() {
             headers.insert(name.unwrap(), value);
@@ -57,9 +81,9 @@ impl HttpClient {
                     .collect::<Vec<_>>().join("\n");
             }
 
-            if let Some(body) = request.trace_body {
+            if let Some(ref body) = request.trace_body {
                 extra_info += "\n";
-                extra_info += &body;
+                extra_info += body;
             }
 
             if extra_info.is_empty() {
@@ -72,46 +96,45 @@ impl HttpClient {
                    method=request.method, url=request.url, extra_info=extra_info);
         }
 
-        let response = self.send_request(
-            request.method, &request.url, headers, request.body, request.timeout)?;
+        let client = Client::builder().timeout(request.timeout).build().map_err(|e| format!(
+            "Unable to create HTTP client: {}", e))?;
 
-        if response.status.is_success() {
-            Ok(request.reply_reader.read(response)?)
-        } else if response.status.is_client_error() || response.status.is_server_error() {
-            Err(HttpClientError::Api(request.error_reader.read(response)?))
-        } else {
-            Err!("Server returned an error: {}", response.status)
+        let mut http_request = client.request(request.method.clone(), &request.url).headers(headers);
+        if let Some(body) = request.body.take() {
+            http_request = http_request.body(body);
         }
+
+        Ok(http_request.send().map_err(humanize_reqwest_error)?)
     }
+}
 
-    fn send_request(&self, method: Method, url: &str, headers: Headers, body: Option<Body>,
-                    timeout: Duration) -> GenericResult<HttpResponse>
-    {
-        let client = Client::builder().timeout(timeout).build().map_err(|e| format!(
-            "Unable to create HTTP client: {}", e))?;
+fn read_response(mut response: Response) -> GenericResult<HttpResponse> {
+    let status = response.status();
 
-        let mut request = client.request(method, url).headers(headers);
-        if let Some(body) = body {
-            request = request.body(body);
-        }
+    let mut body = Vec::new();
+    response.copy_to(&mut body)?;
 
-        let mut response = request.send().map_err(humanize_reqwest_error)?;
-        let status = response.status();
+    if status == StatusCode::NO_CONTENT {
+        trace!("Got {} response.", status);
+    } else {
+        trace!("Got {} response: {}", status,
+           String::from_utf8_lossy(&body).trim_end_matches('\n'));
+    }
 
-        let mut body = Vec::new();
-        response.copy_to(&mut body)?;
+    Ok(HttpResponse {
+        status, body,
+        headers: response.headers().clone(),
+    })
+}
 
-        if status == StatusCode::NO_CONTENT {
-            trace!("Got {} response.", status);
-        } else {
-            trace!("Got {} response: {}", status,
-               String::from_utf8_lossy(&body).trim_end_matches('\n'));
+fn read_error<R, E>(request: &HttpRequest<R, E>, response: HttpResponse) -> HttpClientError<E> {
+    if response.status.is_client_error() || response.status.is_server_error() {
+        match request.error_reader.read(response) {
+            Ok(err) => HttpClientError::Api(err),
+            Err(err) => err.into(),
         }
-
-        Ok(HttpResponse {
-            status, body,
-            headers: response.headers().clone(),
-        })
+    } else {
+        format!("Server returned an error: {}", response.status).into()
     }
 }
 
@@ -0,0 +1,121 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use log::{info, error};
+
+use crate::config::BackupSpecConfig;
+use crate::core::GenericResult;
+use crate::providers::ProviderType;
+use crate::providers::filesystem::Filesystem;
+use crate::storage::{self, BackupTraits, Storage};
+use crate::util::sys::acquire_lock;
+
+struct Archive {
+    path: PathBuf,
+    group_name: String,
+    backup_name: String,
+}
+
+pub fn import(config: &BackupSpecConfig, paths: &[PathBuf]) -> GenericResult<bool> {
+    let upload_config = config.upload.as_ref().ok_or(
+        "Upload is not configured for the specified backup")?;
+
+    let _lock = acquire_lock(&config.path)?;
+    let storage = Storage::new_read_write(Filesystem::new(), &config.path);
+
+    let mut ok = true;
+    let mut archives = Vec::new();
+
+    for path in paths {
+        if fs::metadata(path).map_err(|e| format!("{:?}: {}", path, e))?.is_dir() {
+            ok &= list_group_archives(path, &mut archives)?;
+        } else {
+            archives.push(get_archive(path)?);
+        }
+    }
+
+    archives.sort_by(|a, b| (&a.group_name, &a.backup_name).cmp(&(&b.group_name, &b.backup_name)));
+
+    let (groups, _ok) = storage.get_backup_groups(false)?;
+    let mut group_names: Vec<String> = groups.into_iter().map(|group| group.name).collect();
+    let mut imported = 0;
+
+    for archive in archives {
+        if !group_names.contains(&archive.group_name) {
+            if let Err(err) = storage.create_backup_group(&archive.group_name) {
+                error!("Failed to create {:?} backup group: {}.", archive.group_name, err);
+                ok = false;
+                continue;
+            }
+            group_names.push(archive.group_name.clone());
+        }
+
+        info!("Importing {:?} backup to {:?} backup group...", archive.backup_name, archive.group_name);
+        let group_path = storage.get_backup_group_path(&archive.group_name);
+
+        if let Err(err) = File::open(&archive.path).map_err(Into::into).and_then(|file| {
+            storage::extract_backup(
+                Box::new(file), &archive.backup_name, &upload_config.encryption_passphrase,
+                Path::new(&group_path))
+        }) {
+            error!("Failed to import {:?}: {}.", archive.path, err);
+            ok = false;
+            continue;
+        }
+
+        imported += 1;
+    }
+
+    info!("{} backups have been imported.", imported);
+    Ok(ok)
+}
+
+fn list_group_archives(path: &Path, archives: &mut Vec<Archive>) -> GenericResult<bool> {
+    let traits = BackupTraits::get_for(ProviderType::Cloud);
+    let mut ok = true;
+
+    let mut entries = fs::read_dir(path)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Unable to read {:?}: {}", path, e))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        if entry.file_name().to_str().is_some_and(|name| name.starts_with(traits.temporary_prefix)) {
+            // Temporary or OS-dependent hidden file
+            continue;
+        }
+
+        match get_archive(&entry.path()) {
+            Ok(archive) => archives.push(archive),
+            Err(err) => {
+                error!("{}.", err);
+                ok = false;
+            },
+        }
+    }
+
+    Ok(ok)
+}
+
+fn get_archive(path: &Path) -> GenericResult<Archive> {
+    let traits = BackupTraits::get_for(ProviderType::Cloud);
+    let path = path.canonicalize().map_err(|e| format!("{:?}: {}", path, e))?;
+
+    let file_name = path.file_name().and_then(|name| name.to_str());
+    let group_name = path.parent().and_then(|path| path.file_name()).and_then(|name| name.to_str());
+
+    let backup_name = file_name
+        .filter(|_| path.is_file())
+        .and_then(|name| traits.name_regex.captures(name))
+        .map(|captures| captures.name("name").unwrap().as_str().to_owned())
+        .ok_or_else(|| format!("{:?} doesn't look like a backup archive", path))?;
+
+    let group_name = group_name
+        .filter(|name| traits.group_name_regex.is_match(name))
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| format!(
+            "{:?} doesn't look like a backup archive: it must be located in a backup group directory",
+            path))?;
+
+    Ok(Archive {path, group_name, backup_name})
+}
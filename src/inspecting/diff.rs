@@ -0,0 +1,77 @@
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+use itertools::{EitherOrBoth, Itertools};
+
+use crate::core::GenericResult;
+
+use super::util::{self, format_size};
+
+pub fn diff(backup_path: &Path, other_backup_path: &Path, output: &mut dyn Write) -> GenericResult<bool> {
+    let (storage, backup) = util::open_backup(backup_path)?;
+    let old_files = util::read_metadata(&storage, &backup)?;
+
+    let (storage, backup) = util::open_backup(other_backup_path)?;
+    let new_files = util::read_metadata(&storage, &backup)?;
+
+    let mut added = Stat::default();
+    let mut removed = Stat::default();
+    let mut modified = Stat::default();
+
+    let (mut old_size, mut new_size) = (0, 0);
+
+    for item in old_files.iter().merge_join_by(new_files.iter(), |(a, _), (b, _)| a.cmp(b)) {
+        match item {
+            EitherOrBoth::Left((path, old)) => {
+                writeln!(output, "- {:<7} {:>10} {}", old.status(), format_size(old.size), path.display())?;
+                removed.add(old.size);
+                old_size += old.size;
+            },
+
+            EitherOrBoth::Right((path, new)) => {
+                writeln!(output, "+ {:<7} {:>10} {}", new.status(), format_size(new.size), path.display())?;
+                added.add(new.size);
+                new_size += new.size;
+            },
+
+            EitherOrBoth::Both((path, old), (_, new)) => {
+                if old.hash != new.hash {
+                    writeln!(output, "M {:<7} {:>10} {} (was {})",
+                             new.status(), format_size(new.size), path.display(), format_size(old.size))?;
+                    modified.add(new.size);
+                }
+                old_size += old.size;
+                new_size += new.size;
+            },
+        }
+    }
+
+    writeln!(output)?;
+    writeln!(output, "Added: {}", added)?;
+    writeln!(output, "Removed: {}", removed)?;
+    writeln!(output, "Modified: {}", modified)?;
+    writeln!(output, "Size delta: {}{}",
+             if new_size < old_size {"-"} else {"+"}, format_size(new_size.abs_diff(old_size)))?;
+
+    Ok(true)
+}
+
+#[derive(Default)]
+struct Stat {
+    files: usize,
+    size: u64,
+}
+
+impl Stat {
+    fn add(&mut self, size: u64) {
+        self.files += 1;
+        self.size += size;
+    }
+}
+
+impl fmt::Display for Stat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files ({})", self.files, format_size(self.size))
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, TimeZone};
+use tar::{EntryType, Header};
+
+use crate::core::GenericResult;
+use crate::storage::metadata::MetadataItem;
+use crate::util::sys;
+
+use super::util::{self, format_size};
+
+pub fn list_files(
+    backup_path: &Path, path: &Path, recursive: bool, show_data: bool, output: &mut dyn Write,
+) -> GenericResult<bool> {
+    let prefix = sys::normalize_path(path)?;
+
+    let (storage, backup) = util::open_backup(backup_path)?;
+    let files = util::read_metadata(&storage, &backup)?;
+
+    let found = if show_data {
+        let mut found = false;
+        let mut archive = backup.read_data(storage.provider.read())?;
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+
+            // Chunks are internal entries which are listed in the metadata
+            if entry.header().entry_type() == EntryType::Continuous {
+                continue;
+            }
+
+            let path = Path::new("/").join(entry.path()?);
+
+            if path == prefix || recursive && path.starts_with(&prefix) || path.parent() == Some(&prefix) {
+                writeln!(output, "{}", format_entry(&path, entry.header(), files.get(&path))?)?;
+                found = true;
+            }
+        }
+
+        found
+    } else {
+        list_metadata(&files, &prefix, recursive, output)?
+    };
+
+    if !found {
+        return Err!("{:?} doesn't exist in the backup", prefix);
+    }
+
+    Ok(true)
+}
+
+enum Item<'a> {
+    File(&'a MetadataItem),
+    Directory {files: usize, size: u64},
+}
+
+fn list_metadata(
+    files: &BTreeMap<PathBuf, MetadataItem>, prefix: &Path, recursive: bool, output: &mut dyn Write,
+) -> GenericResult<bool> {
+    let mut items = BTreeMap::new();
+
+    for (path, file) in files {
+        let relative_path = match path.strip_prefix(prefix) {
+            Ok(relative_path) => relative_path,
+            Err(_) => continue,
+        };
+
+        let mut components = relative_path.components();
+        let name = match components.next() {
+            Some(name) if !recursive && components.next().is_some() => name,
+            _ => {
+                items.insert(path.clone(), Item::File(file));
+                continue;
+            },
+        };
+
+        // Directories exist only in data archive, so in metadata mode we derive them from file paths
+        if let Item::Directory {files, size} = items.entry(prefix.join(name)).or_insert(
+            Item::Directory {files: 0, size: 0}
+        ) {
+            *files += 1;
+            *size += file.size;
+        }
+    }
+
+    for (path, item) in &items {
+        match item {
+            Item::File(file) => writeln!(
+                output, "{:<7} {:>10} {:<16} {}",
+                file.status(), format_size(file.size),
+                &file.hash.to_string()[..16], path.display())?,
+
+            Item::Directory {files, size} => writeln!(
+                output, "{:<7} {:>10} {:<16} {}/",
+                "dir", format_size(*size), format!("{} files", files), path.display())?,
+        }
+    }
+
+    Ok(!items.is_empty())
+}
+
+fn format_entry(path: &Path, header: &Header, file: Option<&MetadataItem>) -> GenericResult<String> {
+    let entry_type = header.entry_type();
+
+    let owner = |name: Option<&str>, id: u64| match name {
+        Some(name) if !name.is_empty() => name.to_owned(),
+        _ => id.to_string(),
+    };
+    let owner = format!(
+        "{}:{}", owner(header.username().ok().flatten(), header.uid()?),
+        owner(header.groupname().ok().flatten(), header.gid()?));
+
+    let mtime = header.mtime()?;
+    let mtime = i64::try_from(mtime).ok()
+        .and_then(|mtime| Local.timestamp_opt(mtime, 0).single())
+        .map(|time| time.format("%Y.%m.%d %H:%M:%S").to_string())
+        .ok_or_else(|| format!("Got an invalid modification time for {:?}: {}", path, mtime))?;
+
+    let (status, size) = match file {
+        Some(file) => (file.status(), format_size(file.size)),
+        None => ("-", "-".to_owned()),
+    };
+
+    let mut line = format!(
+        "{} {:<15} {} {:<7} {:>10} {}",
+        format_mode(entry_type, header.mode()?), owner, mtime, status, size, path.display());
+
+    if entry_type == EntryType::Symlink {
+        if let Some(target) = header.link_name()? {
+            line += &format!(" -> {}", target.display());
+        }
+    } else if entry_type == EntryType::Link {
+        if let Some(target) = header.link_name()? {
+            line += &format!(" link to /{}", target.display());
+        }
+    }
+
+    Ok(line)
+}
+
+fn format_mode(entry_type: EntryType, mode: u32) -> String {
+    let mut result = String::with_capacity(10);
+
+    result.push(match entry_type {
+        EntryType::Directory => 'd',
+        EntryType::Symlink => 'l',
+        EntryType::Link => 'h',
+        EntryType::Regular | EntryType::GNUSparse => '-',
+        EntryType::Block => 'b',
+        EntryType::Char => 'c',
+        EntryType::Fifo => 'p',
+        _ => '?',
+    });
+
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = mode >> shift;
+        result.push(if bits & 0o4 != 0 {'r'} else {'-'});
+        result.push(if bits & 0o2 != 0 {'w'} else {'-'});
+        result.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+
+    result
+}
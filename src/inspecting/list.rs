@@ -0,0 +1,149 @@
+use std::fmt;
+use std::io::Write;
+
+use log::info;
+use serde_derive::Serialize;
+
+use crate::config::BackupSpecConfig;
+use crate::core::{EmptyResult, GenericResult};
+use crate::providers::filesystem::Filesystem;
+use crate::storage::{Storage, BackupGroup, Backup};
+use crate::uploading;
+use crate::util::output::{self, OutputFormat};
+
+use super::util::format_size;
+
+#[derive(Serialize)]
+struct StorageReport<'a> {
+    name: &'a str,
+    ok: bool,
+    groups: Vec<BackupGroup>,
+}
+
+pub fn list(
+    config: &BackupSpecConfig, cloud: bool, format: OutputFormat, output: &mut dyn Write,
+) -> GenericResult<bool> {
+    let mut storages = vec![(Storage::new_read_only(Filesystem::new(), &config.path), true)];
+
+    if cloud {
+        let upload_config = config.upload.as_ref().ok_or(
+            "Upload is not configured for the specified backup")?;
+        storages.push((uploading::get_cloud_storage(upload_config)?, false));
+    }
+
+    let mut ok = true;
+    let mut reports = Vec::new();
+
+    for (index, (storage, verify)) in storages.iter().enumerate() {
+        info!("Reading backups on {}...", storage.name());
+        let (groups, storage_ok) = storage.get_backup_groups(*verify).map_err(|e| format!(
+            "Failed to list backup groups on {}: {}", storage.name(), e))?;
+        ok &= storage_ok;
+
+        if format.is_json() {
+            reports.push(StorageReport {name: storage.name(), ok: storage_ok, groups});
+            continue;
+        }
+
+        if index != 0 {
+            writeln!(output)?;
+        }
+        print_storage(storage, &groups, output)?;
+    }
+
+    if format.is_json() {
+        output::print_json(&reports)?;
+    }
+
+    Ok(ok)
+}
+
+fn print_storage(storage: &Storage, groups: &[BackupGroup], output: &mut dyn Write) -> EmptyResult {
+    writeln!(output, "{}:", storage.name())?;
+    if groups.is_empty() {
+        writeln!(output, "  There are no backups.")?;
+    }
+
+    for group in groups {
+        print_group(group, output)?;
+    }
+
+    Ok(())
+}
+
+fn print_group(group: &BackupGroup, output: &mut dyn Write) -> EmptyResult {
+    writeln!(output, "  {}:", group.name)?;
+    if group.backups.is_empty() {
+        writeln!(output, "    The group is empty.")?;
+        return Ok(());
+    }
+
+    let mut total = Stat::default();
+
+    for backup in &group.backups {
+        let mut stat = Stat::default();
+        stat.add(backup);
+        total.add(backup);
+        writeln!(output, "    {}: {}", backup.name, stat)?;
+    }
+
+    writeln!(output, "    Total: {}", total)?;
+    Ok(())
+}
+
+// Aggregated statistics of one or several backups. Cloud backups are opaque archives, so they may
+// have no statistics at all.
+#[derive(Default)]
+struct Stat {
+    inner: bool,
+    unique_files: usize,
+    unique_size: u64,
+    extern_files: usize,
+    extern_size: u64,
+
+    outer: bool,
+    metadata_size: u64,
+    data_size: u64,
+}
+
+impl Stat {
+    fn add(&mut self, backup: &Backup) {
+        if let Some(ref stat) = backup.inner_stat {
+            self.inner = true;
+            self.unique_files += stat.unique_files;
+            self.unique_size += stat.unique_size;
+            self.extern_files += stat.extern_files;
+            self.extern_size += stat.extern_size;
+        }
+
+        if let Some(ref stat) = backup.outer_stat {
+            self.outer = true;
+            self.metadata_size += stat.metadata_size;
+            self.data_size += stat.data_size;
+        }
+    }
+}
+
+impl fmt::Display for Stat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.inner && !self.outer {
+            return f.write_str("no statistics available");
+        }
+
+        if self.inner {
+            write!(f, "{} unique files ({}), {} extern files ({})",
+                   self.unique_files, format_size(self.unique_size),
+                   self.extern_files, format_size(self.extern_size))?;
+        }
+
+        if self.outer {
+            if self.inner {
+                f.write_str("; ")?;
+            }
+            write!(f, "metadata: {}, data: {}",
+                   format_size(self.metadata_size), format_size(self.data_size))?;
+        }
+
+        Ok(())
+    }
+}
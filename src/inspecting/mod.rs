@@ -0,0 +1,14 @@
+mod diff;
+mod files;
+mod list;
+mod status;
+mod util;
+mod verify;
+mod versions;
+
+pub use diff::diff;
+pub use files::list_files;
+pub use list::list;
+pub use status::{Status, status};
+pub use verify::verify;
+pub use versions::versions;
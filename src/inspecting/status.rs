@@ -0,0 +1,165 @@
+use std::fmt;
+
+use itertools::Itertools;
+use log::{Level, error};
+use serde_derive::Serialize;
+
+use crate::config::{Config, BackupSpecConfig};
+use crate::providers::filesystem::Filesystem;
+use crate::storage::Storage;
+use crate::uploading::{self, Finding};
+use crate::util::output::{self, OutputFormat};
+
+// Nagios plugin return codes
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Status {
+    Ok,
+    Unknown,
+    Warning,
+    Critical,
+}
+
+impl Status {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Status::Ok => 0,
+            Status::Warning => 1,
+            Status::Critical => 2,
+            Status::Unknown => 3,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+            Status::Unknown => "UNKNOWN",
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct StatusReport<'a> {
+    status: Status,
+    backups: Vec<BackupStatus<'a>>,
+}
+
+#[derive(Serialize)]
+struct BackupStatus<'a> {
+    name: &'a str,
+    status: Status,
+    summary: String,
+    problems: Vec<Problem>,
+}
+
+#[derive(Serialize)]
+struct Problem {
+    status: Status,
+    message: String,
+}
+
+pub fn status(config: &Config, name: Option<&str>, cloud: bool, format: OutputFormat) -> Status {
+    let mut report = StatusReport {
+        status: Status::Ok,
+        backups: Vec::new(),
+    };
+
+    let backups = match name {
+        Some(name) => match config.get_backup(name) {
+            Ok(backup) => vec![backup],
+            Err(err) => {
+                if format.is_json() {
+                    error!("{}.", err);
+                } else {
+                    println!("{}: {}", Status::Unknown, err);
+                }
+                report.status = Status::Unknown;
+                Vec::new()
+            },
+        },
+        None => config.backups.iter().collect(),
+    };
+
+    for backup in backups {
+        let backup_status = check_backup(backup, cloud);
+        report.status = report.status.max(backup_status.status);
+
+        if format.is_json() {
+            report.backups.push(backup_status);
+        } else {
+            println!("{}: {} - {}", backup.name, backup_status.status, backup_status.summary);
+        }
+    }
+
+    if format.is_json() && let Err(err) = output::print_json(&report) {
+        error!("{}.", err);
+        return Status::Unknown;
+    }
+
+    report.status
+}
+
+fn check_backup(config: &BackupSpecConfig, cloud: bool) -> BackupStatus<'_> {
+    let max_time_without_backups = config.upload.as_ref().and_then(|upload| upload.max_time_without_backups);
+
+    let mut storages = vec![Ok(Storage::new_read_only(Filesystem::new(), &config.path))];
+    if cloud && let Some(upload) = config.upload.as_ref() {
+        storages.push(uploading::get_cloud_storage(upload));
+    }
+
+    let mut problems = Vec::new();
+    let mut summaries = Vec::new();
+
+    for storage in storages {
+        let storage = match storage {
+            Ok(storage) => storage,
+            Err(err) => {
+                problems.push(Problem {status: Status::Unknown, message: err.to_string()});
+                continue;
+            },
+        };
+
+        let (groups, ok) = match storage.get_backup_groups(false) {
+            Ok(result) => result,
+            Err(err) => {
+                problems.push(Problem {status: Status::Unknown, message: format!(
+                    "Failed to list backup groups on {}: {}", storage.name(), err)});
+                continue;
+            },
+        };
+
+        if !ok {
+            problems.push(Problem {
+                status: Status::Warning,
+                message: format!("{} has invalid backups", storage.name()),
+            });
+        }
+
+        problems.extend(uploading::check_backups(&storage, &groups, ok, max_time_without_backups)
+            .into_iter().map(|(level, message): Finding| {
+                let status = if level <= Level::Error {
+                    Status::Critical
+                } else {
+                    Status::Warning
+                };
+                Problem {status, message}
+            }));
+
+        let backups: Vec<_> = groups.iter().flat_map(|group| &group.backups).collect();
+        if let Some(last_backup) = backups.last() {
+            summaries.push(format!(
+                "{}: {} backups, the last one is {}", storage.name(), backups.len(), last_backup.name));
+        }
+    }
+
+    let (status, summary) = match problems.iter().map(|problem| problem.status).max() {
+        Some(status) => (status, problems.iter().map(|problem| &problem.message).join("; ")),
+        None => (Status::Ok, summaries.join("; ")),
+    };
+
+    BackupStatus {name: &config.name, status, summary, problems}
+}
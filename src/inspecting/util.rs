@@ -0,0 +1,30 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use humansize::{self, SizeFormatter};
+
+use crate::core::GenericResult;
+use crate::storage::{Backup, Storage, StorageRc};
+use crate::storage::metadata::MetadataItem;
+
+pub fn open_backup(backup_path: &Path) -> GenericResult<(StorageRc, Backup)> {
+    let (storage, group_name, backup_name) = Storage::open_local_backup(backup_path)?;
+    let backup = storage.get_backup(&group_name, &backup_name)?;
+    Ok((storage, backup))
+}
+
+pub fn read_metadata(storage: &Storage, backup: &Backup) -> GenericResult<BTreeMap<PathBuf, MetadataItem>> {
+    let mut files = BTreeMap::new();
+
+    for file in backup.read_metadata(storage.provider.read())? {
+        let file = file.map_err(|e| format!(
+            "Error while reading {:?} backup metadata: {}", backup.path, e))?;
+        files.insert(PathBuf::from(&file.path), file);
+    }
+
+    Ok(files)
+}
+
+pub fn format_size(size: u64) -> String {
+    SizeFormatter::new(size, humansize::BINARY).to_string()
+}
@@ -0,0 +1,40 @@
+use log::{info, error};
+
+use crate::config::BackupSpecConfig;
+use crate::core::GenericResult;
+use crate::providers::filesystem::Filesystem;
+use crate::storage::Storage;
+
+pub fn verify(config: &BackupSpecConfig) -> GenericResult<bool> {
+    let storage = Storage::new_read_only(Filesystem::new(), &config.path);
+    let provider = storage.provider.read();
+
+    info!("Checking backups metadata...");
+    let (groups, mut ok) = storage.get_backup_groups(true)?;
+
+    let (mut verified, mut corrupted) = (0, 0);
+
+    for group in &groups {
+        for backup in &group.backups {
+            info!("Verifying {:?} backup data...", backup.name);
+            let backup_ok = backup.verify(provider).unwrap_or_else(|e| {
+                error!("Failed to verify {:?} backup: {}.", backup.path, e);
+                false
+            });
+
+            verified += 1;
+            if !backup_ok {
+                corrupted += 1;
+                ok = false;
+            }
+        }
+    }
+
+    if corrupted == 0 {
+        info!("{} backups have been verified: no data corruption found.", verified);
+    } else {
+        error!("{} of {} backups have corrupted data.", corrupted, verified);
+    }
+
+    Ok(ok)
+}
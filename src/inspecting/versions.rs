@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use globset::GlobBuilder;
+use log::{info, error};
+
+use crate::config::BackupSpecConfig;
+use crate::core::GenericResult;
+use crate::providers::filesystem::Filesystem;
+use crate::storage::Storage;
+use crate::util::hash::Hash;
+
+use super::util::format_size;
+
+struct Version<'a> {
+    hash: Hash,
+    size: u64,
+    first_backup: &'a str,
+    last_backup: &'a str,
+    backups: usize,
+}
+
+pub fn versions(config: &BackupSpecConfig, pattern: &str, output: &mut dyn Write) -> GenericResult<bool> {
+    if !pattern.starts_with('/') {
+        return Err!("Invalid path: {:?}. It must be absolute", pattern);
+    }
+
+    let matcher = GlobBuilder::new(pattern)
+        .literal_separator(true).backslash_escape(true)
+        .build().map_err(|e| format!("Invalid glob ({:?}): {}", pattern, e))?
+        .compile_matcher();
+
+    let storage = Storage::new_read_only(Filesystem::new(), &config.path);
+    let provider = storage.provider.read();
+
+    info!("Reading backups metadata...");
+    let (groups, mut ok) = storage.get_backup_groups(false)?;
+
+    let mut files: BTreeMap<String, Vec<Version>> = BTreeMap::new();
+
+    for group in &groups {
+        for backup in &group.backups {
+            let metadata = match backup.read_metadata(provider) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    error!("Failed to read {:?} backup metadata: {}.", backup.path, e);
+                    ok = false;
+                    continue;
+                },
+            };
+
+            for file in metadata {
+                let file = match file {
+                    Ok(file) => file,
+                    Err(e) => {
+                        error!("Error while reading {:?} backup metadata: {}.", backup.path, e);
+                        ok = false;
+                        break;
+                    },
+                };
+
+                if !matcher.is_match(&file.path) {
+                    continue;
+                }
+
+                let versions = files.entry(file.path).or_default();
+
+                match versions.iter_mut().find(|version| version.hash == file.hash) {
+                    Some(version) => {
+                        version.last_backup = &backup.name;
+                        version.backups += 1;
+                    },
+                    None => versions.push(Version {
+                        hash: file.hash,
+                        size: file.size,
+                        first_backup: &backup.name,
+                        last_backup: &backup.name,
+                        backups: 1,
+                    }),
+                }
+            }
+        }
+    }
+
+    if files.is_empty() {
+        return Err!("{:?} doesn't exist in any backup", pattern);
+    }
+
+    for (index, (path, versions)) in files.iter().enumerate() {
+        if index != 0 {
+            writeln!(output)?;
+        }
+
+        writeln!(output, "{}:", path)?;
+        for version in versions {
+            writeln!(
+                output, "  {} - {} ({} backups): {:>10} {:<16}",
+                version.first_backup, version.last_backup, version.backups,
+                format_size(version.size), &version.hash.to_string()[..16])?;
+        }
+    }
+
+    Ok(ok)
+}
@@ -1,8 +1,12 @@
 #[macro_use] mod core;
+mod authorizing;
 mod backuping;
+mod checking;
 mod cli;
 mod config;
 mod http_client;
+mod importing;
+mod inspecting;
 mod providers;
 mod restoring;
 mod storage;
@@ -16,8 +20,11 @@ use std::process;
 use log::error;
 
 use crate::cli::{Action, GlobalOptions, Parser};
+use crate::inspecting::Status;
 use crate::config::Config;
 use crate::core::GenericResult;
+use crate::restoring::RestoreSelection;
+use crate::util::{logging, output};
 
 fn main() {
     let mut parser = Parser::new();
@@ -27,30 +34,106 @@ fn main() {
         process::exit(1);
     });
 
-    if let Err(e) = easy_logging::init(module_path!().split("::").next().unwrap(), global.log_level) {
+    if global.format.is_json() && let Err(e) = output::redirect_stdout() {
+        let _ = writeln!(io::stderr(), "Failed to redirect stdout: {}.", e);
+        process::exit(1);
+    }
+
+    if let Err(e) = logging::init(module_path!().split("::").next().unwrap(), global.log_level) {
         let _ = writeln!(io::stderr(), "Failed to initialize the logging: {}.", e);
         process::exit(1);
     }
 
-    let ok = run(global, parser).unwrap_or_else(|e| {
+    let action = parser.parse().unwrap_or_else(|e| {
         error!("{}.", e);
-        false
+        process::exit(1);
     });
 
-    process::exit(match ok {
-        true => 0,
-        false => 1,
+    let error_exit_code = match action {
+        Action::Status {..} => Status::Unknown.exit_code(),
+        _ => 1,
+    };
+
+    let exit_code = run(global, action).unwrap_or_else(|e| {
+        error!("{}.", e);
+        error_exit_code
     });
+
+    process::exit(exit_code);
 }
 
-fn run(global: GlobalOptions, parser: Parser) -> GenericResult<bool> {
+fn run(global: GlobalOptions, action: Action) -> GenericResult<i32> {
+    // The command is intended to be used to fill in the configuration, so it doesn't require it
+    if let Action::Auth {provider, client_id, client_secret, port, token_url, output_path} = action {
+        authorizing::authorize(
+            provider, &client_id, &client_secret, port, token_url.as_deref(), output_path.as_deref())?;
+        return Ok(0);
+    }
+
     let config_path = &global.config_path;
     let config = Config::load(config_path).map_err(|e| format!(
         "Error while reading {:?} configuration file: {}", config_path, e))?;
 
-    match parser.parse()? {
-        Action::Backup {name} => backuping::backup(config.get_backup(&name)?),
-        Action::Restore {backup_path, restore_path} => restoring::restore(&backup_path, &restore_path),
+    let ok = match action {
+        Action::Auth {..} => unreachable!(),
+        Action::Backup {names, dry_run, parallel} => {
+            let backups = if names.is_empty() {
+                let backups: Vec<_> = config.backups.iter().filter(|backup| backup.backup.is_some()).collect();
+                if backups.is_empty() {
+                    return Err!("There are no backups with configured backup rules");
+                }
+                backups
+            } else {
+                names.iter().map(|name| config.get_backup(name)).collect::<GenericResult<_>>()?
+            };
+
+            let results = backuping::backup_many(&backups, dry_run, parallel);
+
+            if global.format.is_json() {
+                output::print_json(&results)?;
+            } else if dry_run {
+                backuping::print_results(&results);
+            }
+
+            Ok(results.iter().all(|result| result.ok))
+        },
+        Action::CheckConfig => checking::check_config(&config),
+        Action::Cat {backup_path, path} => restoring::cat(&backup_path, &path, &mut io::stdout().lock()),
+        Action::Diff {backup_path, other_backup_path} => inspecting::diff(
+            &backup_path, &other_backup_path, &mut io::stdout()),
+        Action::Export {backup_path, output_path, compress} => restoring::export(
+            &backup_path, output_path.as_deref(), compress),
+
+        Action::Import {name, paths} => importing::import(config.get_backup(&name)?, &paths),
+        Action::List {name, cloud} => inspecting::list(
+            config.get_backup(&name)?, cloud, global.format, &mut io::stdout()),
+        Action::ListFiles {backup_path, path, recursive, long} => inspecting::list_files(
+            &backup_path, &path, recursive, long, &mut io::stdout()),
+        Action::Restore {backup_path, restore_path, paths, filter, cloud} => {
+            let selection = RestoreSelection::new(&paths, filter.as_deref())?;
+
+            let result = match cloud {
+                Some(name) => {
+                    let backup_name = backup_path.to_str().ok_or("Invalid backup name")?;
+                    restoring::restore_from_cloud(config.get_backup(&name)?, backup_name, &restore_path, selection)
+                },
+                None => restoring::restore(&backup_path, &restore_path, selection),
+            }?;
+
+            if global.format.is_json() {
+                output::print_json(&result)?;
+            }
+
+            Ok(result.ok)
+        },
+        Action::Status {name, cloud} => {
+            return Ok(inspecting::status(&config, name.as_deref(), cloud, global.format).exit_code());
+        },
         Action::Upload {verify} => uploading::upload(&config, verify),
-    }
+        Action::Verify {name} => inspecting::verify(config.get_backup(&name)?),
+        Action::Versions {name, path} => inspecting::versions(
+            config.get_backup(&name)?, &path, &mut io::stdout()),
+    }?;
+
+    Ok(if ok {0} else {1})
 }
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt;
+use std::io;
 use std::ops::Add;
 use std::time::Duration;
 
@@ -9,7 +10,8 @@ use serde_derive::{Serialize, Deserialize};
 
 use crate::core::{EmptyResult, GenericResult};
 use crate::http_client::{
-    HttpClient, HttpRequest, HttpRequestBuildingError, Method, Body, EmptyResponse, HttpClientError,
+    HttpClient, HttpRequest, HttpRequestBuildingError, HttpResponse, Method, Body, EmptyResponse,
+    HttpClientError, RawResponseReader, JsonErrorReader,
 };
 use crate::util::hash::{Hasher, ChunkedSha256};
 use crate::util::stream_splitter::{ChunkStreamReceiver, ChunkStream};
@@ -17,7 +19,8 @@ use crate::util::stream_splitter::{ChunkStreamReceiver, ChunkStream};
 use super::{Provider, ProviderType, ReadProvider, WriteProvider, UploadProvider, File, FileType};
 use super::oauth::OauthClient;
 
-const OAUTH_ENDPOINT: &str = "https://www.dropbox.com/oauth2";
+pub const OAUTH_ENDPOINT: &str = "https://www.dropbox.com/oauth2";
+pub const AUTHORIZATION_URL: &str = "https://www.dropbox.com/oauth2/authorize";
 
 const API_ENDPOINT: &str = "https://api.dropboxapi.com/2";
 const API_REQUEST_TIMEOUT: u64 = 15;
@@ -171,6 +174,24 @@ impl ReadProvider for Dropbox {
 
         Ok(Some(files))
     }
+
+    fn open_file(&self, path: &str) -> GenericResult<Box<dyn io::Read + Send>> {
+        #[derive(Serialize)]
+        struct Request<'a> {
+            path: &'a str,
+        }
+
+        let request_json = serde_json::to_string(&Request {path}).map_err(HttpRequestBuildingError::new)?;
+
+        let request = HttpRequest::<HttpResponse, ApiError>::new(
+            Method::POST, CONTENT_ENDPOINT.to_owned() + "/files/download",
+            Duration::from_secs(CONTENT_REQUEST_TIMEOUT),
+            RawResponseReader::new(), JsonErrorReader::new(),
+        ).with_header("Dropbox-API-Arg", request_json)?;
+
+        let request = self.oauth.authenticate(request, "Bearer")?;
+        Ok(self.client.download(request)?)
+    }
 }
 
 impl WriteProvider for Dropbox {
@@ -202,6 +223,10 @@ impl WriteProvider for Dropbox {
 }
 
 impl UploadProvider for Dropbox {
+    fn check_credentials(&self) -> EmptyResult {
+        self.oauth.check()
+    }
+
     fn hasher(&self) -> Box<dyn Hasher> {
         Box::new(ChunkedSha256::new(4 * 1024 * 1024))
     }
@@ -63,7 +63,7 @@ impl ReadProvider for Filesystem {
         Ok(Some(files))
     }
 
-    fn open_file(&self, path: &str) -> GenericResult<Box<dyn io::Read>> {
+    fn open_file(&self, path: &str) -> GenericResult<Box<dyn io::Read + Send>> {
         Ok(Box::new(fs::File::open(path)?))
     }
 }
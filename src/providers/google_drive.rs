@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::io;
 use std::ops::Add;
 use std::time::Duration;
 
@@ -17,13 +18,15 @@ use crate::util::stream_splitter::{ChunkStreamReceiver, ChunkStream};
 use super::{Provider, ProviderType, ReadProvider, WriteProvider, UploadProvider, File, FileType};
 use super::oauth::OauthClient;
 
-const OAUTH_ENDPOINT: &str = "https://accounts.google.com/o/oauth2";
+pub const OAUTH_ENDPOINT: &str = "https://accounts.google.com/o/oauth2";
+pub const AUTHORIZATION_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 
 const API_ENDPOINT: &str = "https://www.googleapis.com/drive/v3";
 const API_REQUEST_TIMEOUT: u64 = 15;
 
 const UPLOAD_ENDPOINT: &str = "https://www.googleapis.com/upload/drive/v3";
 const UPLOAD_REQUEST_TIMEOUT: u64 = 60 * 60;
+const DOWNLOAD_REQUEST_TIMEOUT: u64 = 60 * 60;
 
 pub struct GoogleDrive {
     oauth: OauthClient,
@@ -303,6 +306,22 @@ impl ReadProvider for GoogleDrive {
 
         Ok(Some(files))
     }
+
+    fn open_file(&self, path: &str) -> GenericResult<Box<dyn io::Read + Send>> {
+        let file = self.stat_path(path)?.ok_or("No such file or directory")?;
+        if file.type_() != FileType::File {
+            return Err!("{:?} is not a file", path);
+        }
+
+        let request = self.authenticate(
+            HttpRequest::<HttpResponse, GoogleDriveApiError>::new(
+                Method::GET, API_ENDPOINT.to_owned() + "/files/" + &file.id + "?alt=media",
+                Duration::from_secs(DOWNLOAD_REQUEST_TIMEOUT),
+                RawResponseReader::new(), JsonErrorReader::new())
+        )?;
+
+        Ok(self.client.download(request)?)
+    }
 }
 
 impl WriteProvider for GoogleDrive {
@@ -321,6 +340,10 @@ impl WriteProvider for GoogleDrive {
 }
 
 impl UploadProvider for GoogleDrive {
+    fn check_credentials(&self) -> EmptyResult {
+        self.oauth.check()
+    }
+
     fn hasher(&self) -> Box<dyn Hasher> {
         Box::new(Md5::new())
     }
@@ -3,7 +3,7 @@ pub mod filesystem;
 pub mod google_drive;
 pub mod yandex_disk;
 
-mod oauth;
+pub mod oauth;
 
 use std::fmt;
 use std::io;
@@ -27,7 +27,7 @@ pub trait Provider: Send + Sync {
 pub trait ReadProvider: Provider {
     fn list_directory(&self, path: &str) -> GenericResult<Option<Vec<File>>>;
 
-    fn open_file(&self, _path: &str) -> GenericResult<Box<dyn io::Read>> {
+    fn open_file(&self, _path: &str) -> GenericResult<Box<dyn io::Read + Send>> {
         Err!("{} provider doesn't support file opening functionality", self.name())
     }
 }
@@ -38,6 +38,7 @@ pub trait WriteProvider: Provider {
 }
 
 pub trait UploadProvider: Provider {
+    fn check_credentials(&self) -> EmptyResult;
     fn hasher(&self) -> Box<dyn Hasher>;
     fn max_request_size(&self) -> Option<u64>;
     fn upload_file(&self, directory_path: &str, temp_name: &str, name: &str,
@@ -6,7 +6,7 @@ use std::time::{Instant, Duration};
 use log::debug;
 use serde_derive::{Serialize, Deserialize};
 
-use crate::core::GenericResult;
+use crate::core::{EmptyResult, GenericResult};
 use crate::http_client::{HttpClient, HttpRequest, Method, headers};
 
 pub struct OauthClient {
@@ -48,6 +48,11 @@ impl OauthClient {
             .map_err(|_| "Got an invalid OAuth token")?)
     }
 
+    pub fn check(&self) -> EmptyResult {
+        self.get_access_token().map_err(|e| format!("Unable obtain OAuth token: {}", e))?;
+        Ok(())
+    }
+
     fn get_access_token(&self) -> GenericResult<String> {
         let mut access_token = self.access_token.lock().unwrap();
 
@@ -95,6 +100,35 @@ impl OauthClient {
     }
 }
 
+// Exchanges the authorization code obtained via redirect to the specified URI for a refresh token
+pub fn obtain_refresh_token(
+    token_url: &str, client_id: &str, client_secret: &str, code: &str, redirect_uri: &str,
+) -> GenericResult<String> {
+    #[derive(Serialize)]
+    struct Request<'a> {
+        client_id: &'a str,
+        client_secret: &'a str,
+        code: &'a str,
+        redirect_uri: &'a str,
+        grant_type: &'a str,
+    }
+
+    #[derive(Deserialize)]
+    struct Response {
+        refresh_token: String,
+    }
+
+    let request = HttpRequest::<Response, OauthApiError>::new_json(
+        Method::POST, token_url.to_owned(),
+        Duration::from_secs(API_REQUEST_TIMEOUT)
+    ).with_form(&Request {
+        client_id, client_secret, code, redirect_uri,
+        grant_type: "authorization_code",
+    })?;
+
+    Ok(HttpClient::new().send(request)?.refresh_token)
+}
+
 #[derive(Debug, Deserialize)]
 struct OauthApiError {
     error_description: String,
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::io;
 use std::ops::Add;
 use std::time::{Duration, Instant};
 
@@ -19,12 +20,14 @@ use crate::util::stream_splitter::{ChunkStreamReceiver, ChunkStream};
 use super::{Provider, ProviderType, ReadProvider, WriteProvider, UploadProvider, File, FileType};
 use super::oauth::OauthClient;
 
-const OAUTH_ENDPOINT: &str = "https://oauth.yandex.ru";
+pub const OAUTH_ENDPOINT: &str = "https://oauth.yandex.ru";
+pub const AUTHORIZATION_URL: &str = "https://oauth.yandex.ru/authorize";
 const API_ENDPOINT: &str = "https://cloud-api.yandex.net/v1/disk";
 
 const API_REQUEST_TIMEOUT: u64 = 15;
 const OPERATION_TIMEOUT: u64 = 60;
 const UPLOAD_REQUEST_TIMEOUT: u64 = 60 * 60;
+const DOWNLOAD_REQUEST_TIMEOUT: u64 = 60 * 60;
 
 pub struct YandexDisk {
     oauth: OauthClient,
@@ -236,6 +239,28 @@ impl ReadProvider for YandexDisk {
 
         Ok(Some(files.into_values().collect()))
     }
+
+    fn open_file(&self, path: &str) -> GenericResult<Box<dyn io::Read + Send>> {
+        #[derive(Serialize)]
+        struct Request {
+            path: String,
+        }
+
+        #[derive(Deserialize)]
+        struct Response {
+            href: String,
+        }
+
+        let response: Response = self.api_request(Method::GET, "/resources/download", &Request {
+            path: disk_path(path),
+        })?;
+
+        // The download URL is already signed, so the request doesn't need authentication
+        Ok(self.client.download(HttpRequest::<HttpResponse, ApiError>::new(
+            Method::GET, response.href, Duration::from_secs(DOWNLOAD_REQUEST_TIMEOUT),
+            RawResponseReader::new(), JsonErrorReader::new(),
+        ))?)
+    }
 }
 
 impl WriteProvider for YandexDisk {
@@ -277,6 +302,10 @@ impl WriteProvider for YandexDisk {
 }
 
 impl UploadProvider for YandexDisk {
+    fn check_credentials(&self) -> EmptyResult {
+        self.oauth.check()
+    }
+
     fn hasher(&self) -> Box<dyn Hasher> {
         Box::new(Md5::new())
     }
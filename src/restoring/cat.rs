@@ -0,0 +1,133 @@
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use tar::EntryType;
+
+use crate::core::GenericResult;
+use crate::storage::Storage;
+use crate::util::chunker;
+use crate::util::file_reader::FileReader;
+use crate::util::sys;
+
+use super::plan::{ChunkedFile, RestorePlan};
+use super::selection::RestoreSelection;
+use super::util;
+
+pub fn cat(backup_path: &Path, path: &Path, output: &mut dyn Write) -> GenericResult<bool> {
+    let path = sys::normalize_path(path)?;
+    let selection = RestoreSelection::new(std::slice::from_ref(&path), None)?;
+
+    let (storage, group_name, backup_name) = Storage::open_local_backup(backup_path)?;
+    let (plan, _ok) = RestorePlan::new(&storage, &group_name, &backup_name, &selection)?;
+
+    if let Some(file) = plan.chunked_files.get(&path) {
+        return cat_chunked_file(&storage, &plan, &path, file, output);
+    }
+
+    let found = plan.steps.iter().find_map(|step| {
+        step.files.iter()
+            .find(|(_, file)| file.paths.contains(&path))
+            .map(|(source_path, file)| (step, source_path, file))
+    });
+
+    let (step, source_path, file) = match found {
+        Some(found) => found,
+        None => {
+            if plan.missing_files.contains(&path) {
+                return Err!("{:?} data is missing in the backup group", path);
+            } else if
+                plan.steps.iter().any(|step| !step.files.is_empty()) ||
+                !plan.chunked_files.is_empty() || !plan.missing_files.is_empty()
+            {
+                return Err!("{:?} is a directory", path);
+            } else {
+                return Err!("{:?} doesn't exist in the backup", path);
+            }
+        },
+    };
+
+    let mut archive = step.backup.read_data(storage.provider.read())?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if
+            !matches!(entry.header().entry_type(), EntryType::Regular | EntryType::GNUSparse) ||
+            util::get_file_path_from_tar_path(entry.path()?)? != *source_path
+        {
+            continue;
+        }
+
+        let mut reader = FileReader::new(&mut entry, file.size);
+
+        io::copy(&mut reader, output).and_then(|_| output.flush()).map_err(|e| format!(
+            "Failed to read {:?} from {:?} backup: {}", source_path, step.backup.name, e))?;
+
+        let (bytes_read, hash) = reader.consume();
+        if bytes_read != file.size {
+            return Err!(
+                "{:?} has an unexpected data size in {:?} backup: {} vs {}",
+                source_path, step.backup.name, bytes_read, file.size);
+        }
+
+        if hash != file.hash {
+            return Err!(
+                "{:?} has an unexpected hash in {:?} backup: {} vs {}",
+                source_path, step.backup.name, hash, file.hash);
+        }
+
+        return Ok(true);
+    }
+
+    Err!("{:?} backup data archive doesn't contain {:?}", step.backup.name, source_path)
+}
+
+// Chunks may be stored in any order across the backups, so the file is assembled in a temporary file
+fn cat_chunked_file(
+    storage: &Storage, plan: &RestorePlan, path: &Path, file: &ChunkedFile, output: &mut dyn Write,
+) -> GenericResult<bool> {
+    let mut temp_file = sys::create_temp_file().map_err(|e| format!(
+        "Unable to create a temporary file: {}", e))?;
+    temp_file.set_len(file.size)?;
+
+    for step in &plan.steps {
+        let mut restored_chunks = 0;
+        let mut archive = step.backup.read_data(storage.provider.read())?;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type() != EntryType::Continuous {
+                continue;
+            }
+
+            let hash = chunker::parse_chunk_path(&entry.path()?)?;
+            let Some(chunk) = step.chunks.get(&hash) else {
+                continue;
+            };
+
+            let data = util::read_chunk(&mut entry, &hash, chunk.size)?;
+            for (_path, offset) in &chunk.targets {
+                temp_file.write_all_at(&data, *offset)?;
+            }
+            restored_chunks += 1;
+        }
+
+        if restored_chunks != step.chunks.len() {
+            return Err!("{:?} backup data archive doesn't contain some of {:?} chunks", step.backup.name, path);
+        }
+    }
+
+    temp_file.seek(SeekFrom::Start(0))?;
+
+    let mut reader = FileReader::new(&mut temp_file, file.size);
+
+    io::copy(&mut reader, output).and_then(|_| output.flush()).map_err(|e| format!(
+        "Failed to read {:?}: {}", path, e))?;
+
+    let (bytes_read, hash) = reader.consume();
+    if bytes_read != file.size || hash != file.hash {
+        return Err!("{:?} has been assembled with an unexpected hash: {} vs {}", path, hash, file.hash);
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use humansize::{self, SizeFormatter};
+use log::{info, warn, error};
+
+use crate::config::BackupSpecConfig;
+use crate::core::{EmptyResult, GenericResult};
+use crate::providers::filesystem::Filesystem;
+use crate::storage::{BackupGroup, Storage};
+use crate::uploading;
+use crate::util::{hash::Hash, sys};
+
+use super::restorer::{Restorer, RestoreResult};
+use super::selection::RestoreSelection;
+use super::util;
+
+pub fn restore_from_cloud(
+    config: &BackupSpecConfig, backup_name: &str, restore_dir: &Path, selection: RestoreSelection,
+) -> GenericResult<RestoreResult> {
+    let upload_config = config.upload.as_ref().ok_or(
+        "Upload is not configured for the specified backup")?;
+
+    if fs::symlink_metadata(restore_dir).is_ok() {
+        return Err!("{:?} already exists", restore_dir);
+    }
+
+    let cloud_storage = uploading::get_cloud_storage(upload_config)?;
+
+    info!("Looking for {:?} backup on {}...", backup_name, cloud_storage.name());
+    let (groups, _ok) = cloud_storage.get_backup_groups(false)?;
+    let group = groups.into_iter()
+        .find(|group| group.backups.iter().any(|backup| backup.name == backup_name))
+        .ok_or_else(|| format!("{:?} backup doesn't exist on {}", backup_name, cloud_storage.name()))?;
+
+    // Backup archives have to be downloaded to a local storage first: they contain metadata and data
+    // files in arbitrary order, but the restoring requires metadata before the data. So in the worst case
+    // the restoring needs free space for the whole backup group in addition to the restored files.
+    let download_dir = DownloadDirectory::new(restore_dir)?;
+    check_free_space(&cloud_storage, &group, backup_name, &download_dir.path)?;
+
+    let download_path = download_dir.path.to_str().ok_or_else(|| format!(
+        "Invalid path: {:?}", download_dir.path))?;
+    let local_storage = Storage::new_read_only(Filesystem::new(), download_path);
+
+    let group_path = download_dir.path.join(&group.name);
+    util::create_directory(&group_path)?;
+
+    // Extern file and chunk hashes which data is needed for restoring
+    let mut to_find: Option<(HashSet<Hash>, HashSet<Hash>)> = None;
+
+    for backup in group.backups.iter().rev().skip_while(|backup| backup.name != backup_name) {
+        info!("Downloading {:?} backup from {}...", backup.name, cloud_storage.name());
+        cloud_storage.download_backup(
+            &group.name, &backup.name, &upload_config.encryption_passphrase, &group_path,
+        ).map_err(|e| format!("Failed to download {:?} backup: {}", backup.name, e))?;
+
+        let local_backup = local_storage.get_backup(&group.name, &backup.name)?;
+        let metadata = local_backup.read_metadata(local_storage.provider.read())?;
+
+        match to_find {
+            None => {
+                let (mut extern_hashes, mut unique_hashes) = (HashSet::new(), HashSet::new());
+                let (mut extern_chunks, mut unique_chunks) = (HashSet::new(), HashSet::new());
+
+                for file in metadata {
+                    let file = file?;
+
+                    if let Some(chunks) = file.chunks {
+                        let selected = selection.contains(Path::new(&file.path))?;
+
+                        for chunk in chunks {
+                            if chunk.unique {
+                                unique_chunks.insert(chunk.hash);
+                            } else if selected {
+                                extern_chunks.insert(chunk.hash);
+                            }
+                        }
+                    } else if file.unique {
+                        unique_hashes.insert(file.hash);
+                    } else if file.size != 0 && selection.contains(Path::new(&file.path))? {
+                        extern_hashes.insert(file.hash);
+                    }
+                }
+
+                extern_hashes.retain(|hash| !unique_hashes.contains(hash));
+                extern_chunks.retain(|hash| !unique_chunks.contains(hash));
+
+                to_find = Some((extern_hashes, extern_chunks));
+            },
+
+            Some((ref mut to_find, ref mut to_find_chunks)) => {
+                let mut needed = false;
+
+                for file in metadata {
+                    let file = file?;
+
+                    if let Some(chunks) = file.chunks {
+                        for chunk in chunks {
+                            if chunk.unique && to_find_chunks.remove(&chunk.hash) {
+                                needed = true;
+                            }
+                        }
+                    } else if file.unique && to_find.remove(&file.hash) {
+                        needed = true;
+                    }
+                }
+
+                if !needed {
+                    fs::remove_dir_all(&local_backup.path).map_err(|e| format!(
+                        "Unable to delete {:?}: {}", local_backup.path, e))?;
+                }
+            },
+        }
+
+        if let Some((to_find, to_find_chunks)) = to_find.as_ref() && to_find.is_empty() && to_find_chunks.is_empty() {
+            break;
+        }
+    }
+
+    Restorer::new(&group_path.join(backup_name), selection)?.restore(restore_dir)
+}
+
+// Checks that there is enough free space to download all backups which may be needed for restoring
+fn check_free_space(storage: &Storage, group: &BackupGroup, backup_name: &str, download_path: &Path) -> EmptyResult {
+    let group_path = storage.get_backup_group_path(&group.name);
+    let files = storage.provider.read().list_directory(&group_path)?.ok_or_else(|| format!(
+        "{:?} backup group doesn't exist", group.name))?;
+
+    let sizes: HashMap<String, Option<u64>> = files.into_iter().map(|file| (file.name, file.size)).collect();
+    let mut required_size = 0;
+
+    for backup in group.backups.iter().rev().skip_while(|backup| backup.name != backup_name) {
+        let file_name = backup.path.rsplit('/').next().unwrap();
+
+        match sizes.get(file_name).copied().flatten() {
+            Some(size) => required_size += size,
+            None => {
+                warn!("Unable to get {:?} backup size. Skipping free space checking.", backup.name);
+                return Ok(());
+            },
+        }
+    }
+
+    let free_space = sys::get_free_space(download_path)?;
+    if free_space < required_size {
+        return Err!(
+            "There is not enough free space to download the backups: {} is required, but only {} is available",
+            SizeFormatter::new(required_size, humansize::BINARY), SizeFormatter::new(free_space, humansize::BINARY));
+    }
+
+    Ok(())
+}
+
+struct DownloadDirectory {
+    path: PathBuf,
+}
+
+impl DownloadDirectory {
+    fn new(restore_dir: &Path) -> GenericResult<DownloadDirectory> {
+        let name = restore_dir.file_name().and_then(|name| name.to_str()).ok_or_else(|| format!(
+            "Invalid restore path: {:?}", restore_dir))?;
+
+        let path = restore_dir.with_file_name(format!(".{}.download", name));
+        util::create_directory(&path)?;
+
+        Ok(DownloadDirectory {path})
+    }
+}
+
+impl Drop for DownloadDirectory {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            error!("Failed to delete {:?}: {}.", self.path, err);
+        }
+    }
+}
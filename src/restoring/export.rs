@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use easy_logging::GlobalContext;
+use humansize::{self, SizeFormatter};
+use log::{error, info};
+use tar::EntryType;
+use zstd::stream::write::Encoder;
+
+use crate::core::{EmptyResult, GenericResult};
+use crate::storage::{Storage, StorageRc};
+use crate::util::chunker;
+use crate::util::file_reader::FileReader;
+use crate::util::hash::Hash;
+use crate::util::sparse;
+use crate::util::sys;
+use crate::util::xattr;
+
+use super::plan::{ChunkedFile, RestorePlan, RestoreStep, RestoringFile};
+use super::selection::RestoreSelection;
+use super::util;
+
+// Exports the backup as a plain tar archive with extern files filled in with data from the previous
+// backups.
+//
+// The archive entries are written in their original order (tar sets directory modification time
+// when it leaves the directory), so extern files data and chunks are spooled to a temporary file first.
+pub fn export(backup_path: &Path, output_path: Option<&Path>, compress: bool) -> GenericResult<bool> {
+    let (storage, group_name, backup_name) = Storage::open_local_backup(backup_path)?;
+
+    let output: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(OpenOptions::new()
+            .create(true).truncate(true).write(true).mode(0o600)
+            .open(path).map_err(|e| format!("Unable to create {:?}: {}", path, e))?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let output = BufWriter::new(output);
+
+    let map_write_error = |e: io::Error| -> String {
+        match output_path {
+            Some(path) => format!("Failed to write {:?}: {}", path, e),
+            None => format!("Failed to write the archive to stdout: {}", e),
+        }
+    };
+
+    let (output, ok) = if compress {
+        let (encoder, ok) = Exporter::new(storage, Encoder::new(output, 0)?)?
+            .export(&group_name, &backup_name)?;
+        (encoder.finish().map_err(map_write_error)?, ok)
+    } else {
+        Exporter::new(storage, output)?.export(&group_name, &backup_name)?
+    };
+
+    output.into_inner().map_err(|e| map_write_error(e.into_error()))?
+        .flush().map_err(map_write_error)?;
+
+    Ok(ok)
+}
+
+struct Exporter<W: Write> {
+    storage: StorageRc,
+    archive: tar::Builder<W>,
+
+    spool: File,
+    spool_size: u64,
+    spooled_data: HashMap<Hash, u64>,
+    spooled_chunks: HashMap<Hash, u64>,
+}
+
+impl<W: Write> Exporter<W> {
+    fn new(storage: StorageRc, output: W) -> GenericResult<Exporter<W>> {
+        let spool = sys::create_temp_file().map_err(|e| format!(
+            "Unable to create a temporary file: {}", e))?;
+
+        Ok(Exporter {
+            storage,
+            archive: tar::Builder::new(output),
+
+            spool,
+            spool_size: 0,
+            spooled_data: HashMap::new(),
+            spooled_chunks: HashMap::new(),
+        })
+    }
+
+    fn export(mut self, group_name: &str, backup_name: &str) -> GenericResult<(W, bool)> {
+        let (plan, ok) = RestorePlan::new(
+            &self.storage, group_name, backup_name, &RestoreSelection::default())?;
+
+        let mut extern_files = HashMap::new();
+        let (target, steps) = plan.steps.split_first().unwrap();
+
+        for (index, step) in plan.steps.iter().enumerate() {
+            for (source_path, file) in &step.files {
+                for path in &file.paths {
+                    if index != 0 || path != source_path {
+                        extern_files.insert(path.clone(), file);
+                    }
+                }
+            }
+        }
+
+        for step in steps {
+            let total_size: u64 = step.files.values().map(|file| file.size).sum::<u64>() +
+                step.chunks.values().map(|chunk| chunk.size).sum::<u64>();
+
+            info!("Reading extern files data from {:?} backup ({} unique files, {} chunks, {} total)...",
+                step.backup.name, step.files.len(), step.chunks.len(),
+                SizeFormatter::new(total_size, humansize::BINARY));
+
+            let _context = GlobalContext::new(&step.backup.name);
+            self.spool_step_data(step, |_| true).map_err(|e| format!(
+                "Failed to export {:?} backup: {}", step.backup.path, e))?;
+        }
+
+        // Extern and chunked files may have data in the target backup entries which are located
+        // after them in the archive
+        if target.files.values().any(|file| file.paths.len() > 1) || !target.chunks.is_empty() {
+            info!("Reading extern files data from {:?} backup...", target.backup.name);
+
+            let _context = GlobalContext::new(&target.backup.name);
+            self.spool_step_data(target, |file| file.paths.len() > 1).map_err(|e| format!(
+                "Failed to export {:?} backup: {}", target.backup.path, e))?;
+        }
+
+        info!("Exporting {:?} backup...", target.backup.name);
+
+        let _context = GlobalContext::new(&target.backup.name);
+        let ok = self.export_target_step(target, &extern_files, &plan).map_err(|e| format!(
+            "Failed to export {:?} backup: {}", target.backup.path, e))? && ok;
+
+        Ok((self.archive.into_inner()?, ok))
+    }
+
+    fn spool_step_data<F>(&mut self, step: &RestoreStep, filter: F) -> EmptyResult
+        where F: Fn(&RestoringFile) -> bool
+    {
+        let mut archive = step.backup.read_data(self.storage.provider.read())?;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_type = entry.header().entry_type();
+
+            if entry_type == EntryType::Continuous {
+                let hash = chunker::parse_chunk_path(&entry.path()?)?;
+                if let Some(chunk) = step.chunks.get(&hash) && !self.spooled_chunks.contains_key(&hash) {
+                    let data = util::read_chunk(&mut entry, &hash, chunk.size)?;
+                    self.spool.write_all(&data).map_err(|e| format!(
+                        "Failed to copy {} chunk to a temporary file: {}", hash, e))?;
+
+                    self.spooled_chunks.insert(hash, self.spool_size);
+                    self.spool_size += chunk.size;
+                }
+                continue;
+            } else if !matches!(entry_type, EntryType::Regular | EntryType::GNUSparse) {
+                continue;
+            }
+
+            let file_path = util::get_file_path_from_tar_path(entry.path()?)?;
+            let Some(info) = step.files.get(&file_path).filter(|&info| filter(info)) else {
+                continue;
+            };
+
+            if self.spooled_data.contains_key(&info.hash) {
+                continue;
+            }
+
+            let mut reader = FileReader::new(&mut entry, info.size);
+            io::copy(&mut reader, &mut self.spool).map_err(|e| format!(
+                "Failed to copy {:?} data to a temporary file: {}", file_path, e))?;
+            check_data(&file_path, reader, info.size, &info.hash)?;
+
+            self.spooled_data.insert(info.hash.clone(), self.spool_size);
+            self.spool_size += info.size;
+        }
+
+        Ok(())
+    }
+
+    fn export_target_step(
+        &mut self, step: &RestoreStep, extern_files: &HashMap<PathBuf, &RestoringFile>, plan: &RestorePlan,
+    ) -> GenericResult<bool> {
+        let mut ok = true;
+        let mut archive = step.backup.read_data(self.storage.provider.read())?;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let xattrs = xattr::read_pax(&mut entry)?;
+            let mut header = entry.header().clone();
+            let entry_path = entry.path()?.into_owned();
+            let entry_type = header.entry_type();
+            let file_path = util::get_file_path_from_tar_path(&entry_path)?;
+
+            match entry_type {
+                EntryType::Directory | EntryType::Block | EntryType::Char | EntryType::Fifo => {
+                    xattr::append_pax(&mut self.archive, &xattrs)?;
+                    self.archive.append_data(&mut header, &entry_path, io::empty())?;
+                },
+
+                EntryType::Regular | EntryType::GNUSparse => {
+                    // Sparse files are exported as regular ones
+                    sparse::to_regular(&mut header, entry.size());
+
+                    if let Some(info) = step.files.get(&file_path) {
+                        header.set_size(info.size);
+
+                        let mut reader = FileReader::new(&mut entry, info.size);
+                        xattr::append_pax(&mut self.archive, &xattrs)?;
+                        self.archive.append_data(&mut header, &entry_path, &mut reader)?;
+                        check_data(&file_path, reader, info.size, &info.hash)?;
+                    } else if let Some(info) = extern_files.get(&file_path) {
+                        if entry.size() != 0 {
+                            error!("The backup archive has data for {:?} file which is expected to be external.", file_path);
+                            ok = false;
+                        }
+
+                        let offset = *self.spooled_data.get(&info.hash).ok_or_else(|| format!(
+                            "Data for {:?} extern file hasn't been found", file_path))?;
+
+                        self.spool.seek(SeekFrom::Start(offset))?;
+                        header.set_size(info.size);
+                        xattr::append_pax(&mut self.archive, &xattrs)?;
+                        self.archive.append_data(&mut header, &entry_path, (&mut self.spool).take(info.size))?;
+                    } else if let Some(info) = plan.chunked_files.get(&file_path) {
+                        if entry.size() != 0 {
+                            error!("The backup archive has data for {:?} file which is expected to be chunked.", file_path);
+                            ok = false;
+                        }
+
+                        let mut spooled_file = SpooledChunksReader::new(
+                            &self.spool, &self.spooled_chunks, &file_path, info)?;
+                        let mut reader = FileReader::new(&mut spooled_file, info.size);
+
+                        header.set_size(info.size);
+                        xattr::append_pax(&mut self.archive, &xattrs)?;
+                        self.archive.append_data(&mut header, &entry_path, &mut reader)?;
+                        check_data(&file_path, reader, info.size, &info.hash)?;
+                    } else if !plan.missing_files.contains(&file_path) {
+                        error!("The backup archive contains an unexpected {:?} file. Ignore it.", file_path);
+                        ok = false;
+                    }
+                },
+
+                EntryType::Link => if !plan.missing_files.contains(&file_path) {
+                    let target = entry.link_name()
+                        .map_err(|e| format!("Got an invalid {:?} hard link target path: {}", file_path, e))?
+                        .ok_or_else(|| format!("Got {:?} hard link without target path", file_path))?;
+
+                    self.archive.append_link(&mut header, &entry_path, target)?;
+                },
+
+                EntryType::Symlink => {
+                    let target = entry.link_name()
+                        .map_err(|e| format!("Got an invalid {:?} symlink target path: {}", file_path, e))?
+                        .ok_or_else(|| format!("Got {:?} symlink without target path", file_path))?;
+
+                    xattr::append_pax(&mut self.archive, &xattrs)?;
+                    self.archive.append_link(&mut header, &entry_path, target)?;
+                },
+
+                // Chunks have been spooled and are written as parts of their files
+                EntryType::Continuous => {},
+
+                _ => {
+                    return Err!(
+                        "Got an unsupported archive entry ({:?}): {:?}",
+                        entry_type, entry_path)
+                }
+            }
+        }
+
+        Ok(ok)
+    }
+}
+
+// Reads chunked file data from the spooled chunks
+struct SpooledChunksReader<'a> {
+    spool: &'a File,
+    // Spool offset and size of the chunks left to read
+    chunks: Vec<(u64, u64)>,
+    index: usize,
+}
+
+impl SpooledChunksReader<'_> {
+    fn new<'a>(
+        spool: &'a File, spooled_chunks: &HashMap<Hash, u64>, path: &Path, info: &ChunkedFile,
+    ) -> GenericResult<SpooledChunksReader<'a>> {
+        let mut chunks = Vec::with_capacity(info.chunks.len());
+
+        for chunk in &info.chunks {
+            let offset = *spooled_chunks.get(&chunk.hash).ok_or_else(|| format!(
+                "{} chunk of {:?} file hasn't been found", chunk.hash, path))?;
+            chunks.push((offset, chunk.size));
+        }
+
+        Ok(SpooledChunksReader {spool, chunks, index: 0})
+    }
+}
+
+impl Read for SpooledChunksReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some((offset, size)) = self.chunks.get_mut(self.index) {
+            if *size == 0 {
+                self.index += 1;
+                continue;
+            }
+
+            let max_size = std::cmp::min(buf.len() as u64, *size) as usize;
+            let read_size = self.spool.read_at(&mut buf[..max_size], *offset)?;
+            if read_size == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            *offset += read_size as u64;
+            *size -= read_size as u64;
+
+            return Ok(read_size);
+        }
+
+        Ok(0)
+    }
+}
+
+fn check_data(path: &Path, reader: FileReader, size: u64, expected_hash: &Hash) -> EmptyResult {
+    let (bytes_read, hash) = reader.consume();
+
+    if bytes_read != size {
+        return Err!(
+            "Failed to export {:?}: got an unexpected data size: {} vs {}",
+            path, bytes_read, size);
+    }
+
+    if hash != *expected_hash {
+        return Err!(
+            "Failed to export {:?}: the data has an unexpected hash: {} vs {}",
+            path, hash, expected_hash);
+    }
+
+    Ok(())
+}
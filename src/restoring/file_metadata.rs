@@ -7,11 +7,12 @@ use nix::fcntl::AT_FDCWD;
 use nix::unistd::{Uid, Gid, FchownatFlags};
 
 use crate::core::EmptyResult;
+use crate::util::xattr::{self, Xattrs};
 
-#[derive(Clone, Copy)]
 pub struct FileMetadata {
     pub owner: Option<Owner>,
     pub mode: Option<u32>,
+    pub xattrs: Xattrs,
     pub mtime: i64,
 }
 
@@ -37,6 +38,10 @@ impl FileMetadata {
                 "Unable to change {path:?} permissions: {e}"))?;
         }
 
+        // Must be set after ownership change which drops file capabilities and permissions change
+        // which modifies ACL
+        xattr::set(path, &self.xattrs)?;
+
         let time = FileTime::from_unix_time(self.mtime, 0);
         filetime::set_symlink_file_times(path, time, time).map_err(|e| format!(
             "Unable to change {path:?} modification time: {e}"))?;
@@ -1,7 +1,11 @@
+mod cat;
+mod cloud;
+mod export;
 mod file_metadata;
 mod multi_writer;
 mod plan;
 mod restorer;
+mod selection;
 mod users;
 mod util;
 
@@ -11,6 +15,12 @@ use crate::core::GenericResult;
 
 use restorer::Restorer;
 
-pub fn restore(backup_path: &Path, restore_dir: &Path) -> GenericResult<bool> {
-    Restorer::new(backup_path)?.restore(restore_dir)
+pub use cat::cat;
+pub use cloud::restore_from_cloud;
+pub use export::export;
+pub use restorer::RestoreResult;
+pub use selection::RestoreSelection;
+
+pub fn restore(backup_path: &Path, restore_dir: &Path, selection: RestoreSelection) -> GenericResult<RestoreResult> {
+    Restorer::new(backup_path, selection)?.restore(restore_dir)
 }
\ No newline at end of file
@@ -1,30 +1,89 @@
+use std::cmp;
 use std::fs::File;
-use std::io::Write;
+use std::io::{Seek, SeekFrom, Write};
 
 use crate::util::sys;
 
 pub struct MultiWriter {
     files: Vec<File>,
+    // Skip zero blocks creating holes instead of them
+    sparse: bool,
+    position: u64,
 }
 
 impl MultiWriter {
-    pub fn new(files: Vec<File>) -> MultiWriter {
-        MultiWriter {files}
+    const SPARSE_BLOCK_SIZE: u64 = 4096;
+
+    pub fn new(files: Vec<File>, sparse: bool) -> MultiWriter {
+        MultiWriter {files, sparse, position: 0}
     }
 
     pub fn close(self) -> nix::Result<()> {
         for file in self.files {
+            // The file may end with a hole
+            if self.sparse {
+                nix::unistd::ftruncate(&file, self.position as libc::off_t)?;
+            }
             sys::close_file(file)?;
         }
         Ok(())
     }
+
+    fn write_data(&mut self, data: &[u8]) -> std::io::Result<()> {
+        for file in &mut self.files {
+            file.write_all(data)?;
+        }
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    fn skip_zeros(&mut self, size: u64) -> std::io::Result<()> {
+        self.position += size;
+        for file in &mut self.files {
+            file.seek(SeekFrom::Start(self.position))?;
+        }
+        Ok(())
+    }
 }
 
 impl Write for MultiWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        for file in &mut self.files {
-            file.write_all(buf)?;
+        if !self.sparse {
+            self.write_data(buf)?;
+            return Ok(buf.len());
+        }
+
+        // Split the data into runs of data and zero blocks aligned to the filesystem block size
+        let mut data = buf;
+
+        while !data.is_empty() {
+            let mut run_size = 0;
+            let mut run_zeros = None;
+
+            while run_size < data.len() {
+                let position = self.position + run_size as u64;
+                let block_size = cmp::min(
+                    Self::SPARSE_BLOCK_SIZE - position % Self::SPARSE_BLOCK_SIZE,
+                    (data.len() - run_size) as u64) as usize;
+
+                let block = &data[run_size..run_size + block_size];
+                let zeros = block.iter().all(|&byte| byte == 0);
+
+                if *run_zeros.get_or_insert(zeros) != zeros {
+                    break;
+                }
+                run_size += block_size;
+            }
+
+            let (run, rest) = data.split_at(run_size);
+            if run_zeros == Some(true) {
+                self.skip_zeros(run.len() as u64)?;
+            } else {
+                self.write_data(run)?;
+            }
+            data = rest;
         }
+
         Ok(buf.len())
     }
 
@@ -34,4 +93,4 @@ impl Write for MultiWriter {
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,21 +1,31 @@
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
-use log::{error, info};
+use log::{error, info, warn};
 
 use crate::core::{GenericError, GenericResult};
 use crate::storage::{Storage, Backup};
+use crate::storage::metadata::Chunk;
 use crate::util::hash::Hash;
 
+use super::selection::RestoreSelection;
+
 pub struct RestorePlan {
     pub steps: Vec<RestoreStep>,
     pub extern_files: HashSet<PathBuf>,
     pub missing_files: HashSet<PathBuf>,
+    // Parent directories of the selected files (collected only on partial restore)
+    pub directories: HashSet<PathBuf>,
+    // Hard links to create after restoring the data (path -> target)
+    pub hard_links: HashMap<PathBuf, PathBuf>,
+    // Files which are assembled from chunks
+    pub chunked_files: HashMap<PathBuf, ChunkedFile>,
 }
 
 pub struct RestoreStep {
     pub backup: Backup,
     pub files: HashMap<PathBuf, RestoringFile>,
+    pub chunks: HashMap<Hash, RestoringChunk>,
 }
 
 pub struct RestoringFile {
@@ -24,8 +34,22 @@ pub struct RestoringFile {
     pub paths: Vec<PathBuf>,
 }
 
+pub struct ChunkedFile {
+    pub hash: Hash,
+    pub size: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+pub struct RestoringChunk {
+    pub size: u64,
+    // Files and offsets to write the chunk to
+    pub targets: Vec<(PathBuf, u64)>,
+}
+
 impl RestorePlan {
-    pub fn new(storage: &Storage, group_name: &str, backup_name: &str) -> GenericResult<(RestorePlan, bool)> {
+    pub fn new(
+        storage: &Storage, group_name: &str, backup_name: &str, selection: &RestoreSelection,
+    ) -> GenericResult<(RestorePlan, bool)> {
         let mut ok = true;
 
         let provider = storage.provider.read();
@@ -33,7 +57,11 @@ impl RestorePlan {
 
         let mut steps = Vec::new();
         let mut extern_files: HashSet<PathBuf> = HashSet::new();
+        let mut directories = HashSet::new();
+        let mut hard_links = HashMap::new();
+        let mut chunked_files = HashMap::new();
         let mut to_find: HashMap<Hash, Vec<PathBuf>> = HashMap::new();
+        let mut to_find_chunks: HashMap<Hash, Vec<(PathBuf, u64)>> = HashMap::new();
 
         info!("Building restoring plan...");
 
@@ -47,57 +75,136 @@ impl RestorePlan {
             };
 
             let mut to_restore = HashMap::new();
+            let mut chunks_to_restore = HashMap::new();
 
             if steps.is_empty() {
                 let mut own_files = Vec::new();
+                let mut own_chunks = Vec::new();
+                // File ID -> path and whether it's selected
+                let mut inodes: HashMap<(u64, u64), (PathBuf, bool)> = HashMap::new();
+                // Not selected hard link targets -> the first selected hard link which gets the data
+                let mut substitutes: HashMap<PathBuf, PathBuf> = HashMap::new();
 
                 for file in backup.read_metadata(provider).map_err(map_read_error)? {
                     let file = file.map_err(map_read_error)?;
                     let path = PathBuf::from(file.path);
 
+                    let selected = !selection.is_partial() || selection.contains(&path)?;
+                    if selected && selection.is_partial() {
+                        for directory in path.ancestors().skip(1) {
+                            if !directories.insert(directory.to_owned()) {
+                                break;
+                            }
+                        }
+                    }
+
+                    if file.hard_link {
+                        if !selected {
+                            continue;
+                        }
+
+                        // Hard link target always precedes its links
+                        if let Some((target, target_selected)) = inodes.get(&file.fingerprint.file_id()) {
+                            if *target_selected {
+                                hard_links.insert(path, target.clone());
+                                continue;
+                            } else if let Some(substitute) = substitutes.get(target) {
+                                hard_links.insert(path, substitute.clone());
+                                continue;
+                            }
+                            substitutes.insert(target.clone(), path.clone());
+                        }
+
+                        // Restore the file data as for an extern file
+                        match file.chunks {
+                            Some(chunks) => add_chunked_file(
+                                &mut chunked_files, &mut to_find_chunks, path, file.hash, file.size, chunks),
+                            None => to_find.entry(file.hash).or_default().push(path),
+                        }
+                        continue;
+                    }
+
+                    if let Some(chunks) = file.chunks {
+                        // Unique chunks of not selected files are still needed as a possible data
+                        // source for the selected chunked files.
+                        for chunk in &chunks {
+                            if chunk.unique {
+                                own_chunks.push((chunk.hash.clone(), chunk.size));
+                            }
+                        }
+
+                        inodes.insert(file.fingerprint.file_id(), (path.clone(), selected));
+                        if selected {
+                            add_chunked_file(&mut chunked_files, &mut to_find_chunks, path, file.hash, file.size, chunks);
+                        }
+                        continue;
+                    }
+
+                    inodes.insert(file.fingerprint.file_id(), (path.clone(), selected));
+
+                    // Not selected unique files are still needed as a possible data source for
+                    // the selected extern files.
                     if file.unique || file.size == 0 {
-                        own_files.push((path, file.hash, file.size));
-                    } else {
+                        own_files.push((path, file.hash, file.size, selected));
+                    } else if selected {
                         to_find.entry(file.hash).or_default().push(path);
                     }
                 }
 
-                for (path, hash, size) in own_files {
+                for (path, hash, size, selected) in own_files {
                     let mut paths = to_find.remove(&hash).unwrap_or_default();
                     extern_files.extend(paths.iter().cloned());
 
-                    paths.reserve_exact(1);
-                    paths.push(path.clone());
-                    to_restore.insert(path, RestoringFile {hash, size, paths});
+                    if selected {
+                        paths.reserve_exact(1);
+                        paths.push(path.clone());
+                    }
+
+                    if !paths.is_empty() {
+                        to_restore.insert(path, RestoringFile {hash, size, paths});
+                    }
+                }
+
+                for (hash, size) in own_chunks {
+                    if let Some(targets) = to_find_chunks.remove(&hash) {
+                        chunks_to_restore.insert(hash, RestoringChunk {size, targets});
+                    }
+                }
+
+                if selection.is_partial() && to_restore.is_empty() && to_find.is_empty() && chunked_files.is_empty() {
+                    warn!("There are no files matching the specified paths in the backup.");
                 }
             } else {
-                if to_find.is_empty() {
+                if to_find.is_empty() && to_find_chunks.is_empty() {
                     break;
                 }
 
                 for file in backup.read_metadata(provider).map_err(map_read_error)? {
                     let file = file.map_err(map_read_error)?;
-                    if !file.unique {
-                        continue;
-                    }
 
-                    if let Some(paths) = to_find.remove(&file.hash) {
+                    if let Some(chunks) = file.chunks {
+                        for chunk in chunks {
+                            if chunk.unique && let Some(targets) = to_find_chunks.remove(&chunk.hash) {
+                                chunks_to_restore.insert(chunk.hash, RestoringChunk {size: chunk.size, targets});
+                            }
+                        }
+                    } else if file.unique && let Some(paths) = to_find.remove(&file.hash) {
                         extern_files.extend(paths.iter().cloned());
                         to_restore.insert(file.path.into(), RestoringFile {
                             hash: file.hash,
                             size: file.size,
                             paths
                         });
+                    }
 
-                        if to_find.is_empty() {
-                            break;
-                        }
+                    if to_find.is_empty() && to_find_chunks.is_empty() {
+                        break;
                     }
                 }
             }
 
-            if steps.is_empty() || !to_restore.is_empty() {
-                steps.push(RestoreStep {backup, files: to_restore});
+            if steps.is_empty() || !to_restore.is_empty() || !chunks_to_restore.is_empty() {
+                steps.push(RestoreStep {backup, files: to_restore, chunks: chunks_to_restore});
             }
         }
 
@@ -110,6 +217,29 @@ impl RestorePlan {
             missing_files.extend(paths);
         }
 
+        for targets in to_find_chunks.into_values() {
+            for (path, _offset) in targets {
+                chunked_files.remove(&path);
+                missing_files.insert(path);
+            }
+        }
+
+        for step in &mut steps {
+            step.chunks.retain(|_, chunk| {
+                chunk.targets.retain(|(path, _offset)| !missing_files.contains(path));
+                !chunk.targets.is_empty()
+            });
+        }
+
+        let missing_links: Vec<PathBuf> = hard_links.iter()
+            .filter(|(_, target)| missing_files.contains(*target))
+            .map(|(path, _)| path.clone()).collect();
+
+        for path in missing_links {
+            hard_links.remove(&path);
+            missing_files.insert(path);
+        }
+
         if !missing_files.is_empty() {
             error!("The following files aren't recoverable (missing extern data):");
             for path in &missing_files {
@@ -118,6 +248,20 @@ impl RestorePlan {
             ok = false;
         }
 
-        Ok((RestorePlan {steps, extern_files, missing_files}, ok))
+        Ok((RestorePlan {steps, extern_files, missing_files, directories, hard_links, chunked_files}, ok))
+    }
+}
+
+fn add_chunked_file(
+    chunked_files: &mut HashMap<PathBuf, ChunkedFile>, to_find_chunks: &mut HashMap<Hash, Vec<(PathBuf, u64)>>,
+    path: PathBuf, hash: Hash, size: u64, chunks: Vec<Chunk>,
+) {
+    let mut offset = 0;
+
+    for chunk in &chunks {
+        to_find_chunks.entry(chunk.hash.clone()).or_default().push((path.clone(), offset));
+        offset += chunk.size;
     }
+
+    chunked_files.insert(path, ChunkedFile {hash, size, chunks});
 }
\ No newline at end of file
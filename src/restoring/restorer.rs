@@ -1,72 +1,71 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
 use std::io::{self, Read, Write};
-use std::os::unix::{self, fs::OpenOptionsExt};
+use std::os::unix::{self, fs::{FileExt, OpenOptionsExt}};
 use std::path::{Path, PathBuf};
 
 use easy_logging::GlobalContext;
 use humansize::{self, SizeFormatter};
 use itertools::Itertools;
-use log::{error, info, debug};
+use log::{error, info, debug, warn};
+use serde_derive::Serialize;
+use nix::errno::Errno;
+use nix::sys::stat::{Mode, SFlag};
 use tar::{Entry, EntryType, Header};
 
 use crate::core::{EmptyResult, GenericResult};
-use crate::providers::filesystem::Filesystem;
 use crate::storage::{Storage, StorageRc};
+use crate::util::chunker;
 use crate::util::file_reader::FileReader;
+use crate::util::hash::Hash;
 use crate::util::sys;
+use crate::util::xattr::{self, Xattrs};
 
 use super::file_metadata::{FileMetadata, Owner};
 use super::multi_writer::MultiWriter;
-use super::plan::{RestorePlan, RestoreStep, RestoringFile};
+use super::plan::{ChunkedFile, RestorePlan, RestoreStep, RestoringChunk, RestoringFile};
+use super::selection::RestoreSelection;
 use super::users::UsersCache;
 use super::util::{self, get_restore_path};
 
+#[derive(Serialize)]
+pub struct RestoreResult {
+    pub ok: bool,
+    pub restored_files: usize,
+    pub missing_files: Vec<PathBuf>,
+    // Special files which we aren't permitted to create
+    pub skipped_files: Vec<PathBuf>,
+}
+
 pub struct Restorer {
     storage: StorageRc,
     group_name: String,
     backup_name: String,
+    selection: RestoreSelection,
 
     users: Option<UsersCache>,
     pending_extern_files: HashSet<PathBuf>,
     restored_extern_files: HashSet<PathBuf>,
     missing_extern_files: HashSet<PathBuf>,
+    hard_links: HashMap<PathBuf, PathBuf>,
+    chunked_files: HashMap<PathBuf, ChunkedFile>,
+    // Chunked files which data hasn't been found
+    missing_chunked_files: HashSet<PathBuf>,
     pre_created_directories: HashSet<PathBuf>,
+    required_directories: HashSet<PathBuf>,
+    restored_directories: HashSet<PathBuf>,
+    restored_files: usize,
+    skipped_files: Vec<PathBuf>,
     scheduled_file_metadata: Vec<(PathBuf, FileMetadata)>,
 }
 
 impl Restorer {
-    pub fn new(backup_path: &Path) -> GenericResult<Restorer> {
-        let backup_path = backup_path.canonicalize().map_err(|e| format!(
-            "Invalid backup path: {}", e))?;
-
-        let (backup_root, group_name, backup_name) = {
-            let backup_name = backup_path.file_name().and_then(|name| name.to_str());
-            let group_path = backup_path.parent();
-            let group_name = group_path.and_then(|path| path.file_name()).and_then(|name| name.to_str());
-            let backup_root = group_path.and_then(|path| path.parent()).and_then(|name| name.to_str());
-
-            match (backup_root, group_name, backup_name) {
-                (Some(root), Some(group_name), Some(backup_name)) => (root, group_name, backup_name),
-                _ => return Err!("Invalid backup path"),
-            }
-        };
-
-        let storage = Storage::new_read_only(Filesystem::new(), backup_root);
-        let backup_traits = storage.backup_traits();
-
-        if
-            !backup_traits.group_name_regex.is_match(group_name) ||
-            !backup_traits.name_regex.is_match(backup_name)
-        {
-            return Err!("{:?} doesn't look like backup path", backup_path)
-        }
+    pub fn new(backup_path: &Path, selection: RestoreSelection) -> GenericResult<Restorer> {
+        let (storage, group_name, backup_name) = Storage::open_local_backup(backup_path)?;
 
         Ok(Restorer {
-            storage,
-            group_name: group_name.to_owned(),
-            backup_name: backup_name.to_owned(),
+            storage, group_name, backup_name, selection,
 
             users: if nix::unistd::geteuid().is_root() {
                 Some(UsersCache::new())
@@ -77,31 +76,65 @@ impl Restorer {
             pending_extern_files: HashSet::new(),
             restored_extern_files: HashSet::new(),
             missing_extern_files: HashSet::new(),
+            hard_links: HashMap::new(),
+            chunked_files: HashMap::new(),
+            missing_chunked_files: HashSet::new(),
             pre_created_directories: HashSet::new(),
+            required_directories: HashSet::new(),
+            restored_directories: HashSet::new(),
+            restored_files: 0,
+            skipped_files: Vec::new(),
             scheduled_file_metadata: Vec::new(),
         })
     }
 
-    pub fn restore(mut self, restore_dir: &Path) -> GenericResult<bool> {
-        let (plan, mut ok) = RestorePlan::new(&self.storage, &self.group_name, &self.backup_name)?;
+    pub fn restore(mut self, restore_dir: &Path) -> GenericResult<RestoreResult> {
+        let (plan, mut ok) = RestorePlan::new(
+            &self.storage, &self.group_name, &self.backup_name, &self.selection)?;
         self.pending_extern_files = plan.extern_files;
         self.missing_extern_files = plan.missing_files;
+        self.required_directories = plan.directories;
+        self.hard_links = plan.hard_links;
+        self.chunked_files = plan.chunked_files;
 
         util::create_directory(restore_dir)?;
+        self.create_chunked_files(restore_dir)?;
 
         for (index, step) in plan.steps.iter().enumerate() {
             let total_size: u64 = step.files.values().map(|file| file.size).sum();
+            let chunks_size: u64 = step.chunks.values().map(|chunk| chunk.size).sum();
 
-            info!("Restoring data from {:?} backup ({} unique files {} total)...",
-                step.backup.name, step.files.len(),
-                SizeFormatter::new(total_size, humansize::BINARY));
+            if step.chunks.is_empty() {
+                info!("Restoring data from {:?} backup ({} unique files {} total)...",
+                    step.backup.name, step.files.len(),
+                    SizeFormatter::new(total_size, humansize::BINARY));
+            } else {
+                info!("Restoring data from {:?} backup ({} unique files {} total, {} chunks {} total)...",
+                    step.backup.name, step.files.len(),
+                    SizeFormatter::new(total_size, humansize::BINARY), step.chunks.len(),
+                    SizeFormatter::new(chunks_size, humansize::BINARY));
+            }
 
             let _context = GlobalContext::new(&step.backup.name);
             ok &= self.process_step(step, index == 0, restore_dir).map_err(|e| format!(
                 "Failed to restore {:?} backup: {}", step.backup.path, e))?;
         }
 
-        let missing_extern_data = self.pending_extern_files;
+        let mut missing_extern_data = self.pending_extern_files;
+        missing_extern_data.extend(self.missing_chunked_files);
+
+        for (path, target) in &self.hard_links {
+            if missing_extern_data.contains(target) {
+                missing_extern_data.insert(path.clone());
+                continue;
+            }
+
+            let restore_path = get_restore_path(restore_dir, path)?;
+            fs::hard_link(get_restore_path(restore_dir, target)?, &restore_path).map_err(|e| format!(
+                "Unable to create {:?} hard link: {}", restore_path, e))?;
+            self.restored_files += 1;
+        }
+
         for (path, metadata) in self.scheduled_file_metadata.iter().rev() {
             if !missing_extern_data.contains(path) {
                 metadata.set(get_restore_path(restore_dir, path)?)?;
@@ -110,7 +143,7 @@ impl Restorer {
 
         if !missing_extern_data.is_empty() {
             error!("Failed to restore the following files (missing extern data):");
-            for path in missing_extern_data {
+            for path in &missing_extern_data {
                 error!("* {}", path.display())
             }
             ok = false;
@@ -124,46 +157,90 @@ impl Restorer {
             ok = false;
         }
 
-        Ok(ok)
+        if !self.skipped_files.is_empty() {
+            warn!("The following special files haven't been restored (insufficient privileges to create them):");
+            for path in &self.skipped_files {
+                warn!("* {}", path.display());
+            }
+        }
+
+        let mut missing_files: Vec<PathBuf> = missing_extern_data.into_iter()
+            .chain(self.missing_extern_files).collect();
+        missing_files.sort();
+
+        Ok(RestoreResult {
+            ok, restored_files: self.restored_files, missing_files,
+            skipped_files: self.skipped_files,
+        })
     }
 
     fn process_step(&mut self, step: &RestoreStep, is_target: bool, restore_dir: &Path) -> GenericResult<bool> {
         let mut ok = true;
+        let mut restored_chunks = HashSet::new();
         let mut archive = step.backup.read_data(self.storage.provider.read())?;
 
         for entry in archive.entries()? {
-            let entry = entry?;
+            let mut entry = entry?;
+            let xattrs = xattr::read_pax(&mut entry)?;
             let header = entry.header();
             let entry_path = entry.path()?;
             let entry_type = header.entry_type();
             let file_path = util::get_file_path_from_tar_path(&entry_path)?;
 
             match entry_type {
-                EntryType::Directory => if is_target {
+                EntryType::Directory => if is_target && self.select_directory(&file_path)? {
                     if !self.pre_created_directories.remove(&file_path) {
                         util::create_directory(get_restore_path(restore_dir, &file_path)?)?;
                     }
-                    self.schedule_file_metadata_change(file_path, header)?;
+                    self.schedule_file_metadata_change(file_path, header, xattrs)?;
                 }
 
-                EntryType::Regular => {
+                EntryType::Regular | EntryType::GNUSparse => {
                     if let Some(info) = step.files.get(&file_path) {
-                        self.restore_files(&file_path, entry, info, restore_dir, is_target)?;
+                        self.restore_files(&file_path, entry, xattrs, info, restore_dir, is_target)?;
                     } else if is_target {
-                        if self.pending_extern_files.contains(&file_path) || self.restored_extern_files.contains(&file_path) {
+                        if self.chunked_files.contains_key(&file_path) {
+                            if entry.size() != 0 {
+                                error!("The backup archive has data for {:?} file which is expected to be chunked.", file_path);
+                                ok = false;
+                            }
+                            self.schedule_file_metadata_change(file_path, header, xattrs)?;
+                        } else if self.pending_extern_files.contains(&file_path) || self.restored_extern_files.contains(&file_path) {
                             if entry.size() != 0 {
                                 error!("The backup archive has data for {:?} file which is expected to be external.", file_path);
                                 ok = false;
                             }
-                            self.schedule_file_metadata_change(file_path, header)?;
-                        } else if !self.missing_extern_files.contains(&file_path) {
+                            self.schedule_file_metadata_change(file_path, header, xattrs)?;
+                        } else if !self.missing_extern_files.contains(&file_path) && self.selection.contains(&file_path)? {
                             error!("The backup archive contains an unexpected {:?} file. Ignore it.", file_path);
                             ok = false;
                         }
                     }
                 },
 
-                EntryType::Symlink => if is_target {
+                // Hard links are created after restoring all the data, but may be restored as extern
+                // files on partial restore if their targets aren't selected
+                EntryType::Link => if is_target && !self.hard_links.contains_key(&file_path) {
+                    if
+                        self.pending_extern_files.contains(&file_path) || self.restored_extern_files.contains(&file_path) ||
+                        self.chunked_files.contains_key(&file_path)
+                    {
+                        self.schedule_file_metadata_change(file_path, header, xattrs)?;
+                    } else if !self.missing_extern_files.contains(&file_path) && self.selection.contains(&file_path)? {
+                        error!("The backup archive contains an unexpected {:?} hard link. Ignore it.", file_path);
+                        ok = false;
+                    }
+                },
+
+                EntryType::Continuous => {
+                    let hash = chunker::parse_chunk_path(&entry_path)?;
+                    if let Some(chunk) = step.chunks.get(&hash) {
+                        self.restore_chunk(&hash, entry, chunk, restore_dir)?;
+                        restored_chunks.insert(hash);
+                    }
+                },
+
+                EntryType::Symlink => if is_target && self.select_entry(&file_path)? {
                     let target = entry.link_name()
                         .map_err(|e| format!("Got an invalid {:?} symlink target path: {}", file_path, e))?
                         .ok_or_else(|| format!("Got {:?} symlink without target path", file_path))?;
@@ -172,7 +249,16 @@ impl Restorer {
                     unix::fs::symlink(target, &restore_path).map_err(|e| format!(
                         "Unable to create {:?} symlink: {}", restore_path, e))?;
 
-                    self.get_file_metadata(header)?.set(&restore_path)?;
+                    self.get_file_metadata(header, xattrs)?.set(&restore_path)?;
+                },
+
+                EntryType::Block | EntryType::Char | EntryType::Fifo => if is_target && self.select_entry(&file_path)? {
+                    let restore_path = get_restore_path(restore_dir, &file_path)?;
+                    if self.restore_special_file(&restore_path, header)? {
+                        self.get_file_metadata(header, xattrs)?.set(&restore_path)?;
+                    } else {
+                        self.skipped_files.push(file_path);
+                    }
                 },
 
                 _ => {
@@ -183,17 +269,84 @@ impl Restorer {
             }
         }
 
+        for (hash, chunk) in &step.chunks {
+            if !restored_chunks.contains(hash) {
+                error!("The backup archive has no {} chunk.", hash);
+                self.missing_chunked_files.extend(chunk.targets.iter().map(|(path, _offset)| path.clone()));
+                ok = false;
+            }
+        }
+
         Ok(ok)
     }
 
+    fn select_directory(&mut self, path: &Path) -> GenericResult<bool> {
+        if !self.selection.is_partial() {
+            return Ok(true);
+        }
+
+        let selected =
+            self.required_directories.contains(path) || self.selection.is_prefix_parent(path) ||
+            self.select_entry(path)?;
+
+        if selected {
+            self.restored_directories.insert(path.to_owned());
+        }
+
+        Ok(selected)
+    }
+
+    fn select_entry(&self, path: &Path) -> GenericResult<bool> {
+        if !self.selection.is_partial() {
+            return Ok(true);
+        }
+
+        // Directory entries precede their contents in the archive, so on partial restore we restore
+        // only entries which directories have been restored
+        let parent_restored = match path.parent() {
+            Some(parent) => sys::is_root_path(parent) || self.restored_directories.contains(parent),
+            None => false,
+        };
+
+        Ok(parent_restored && self.selection.contains(path)?)
+    }
+
+    // Returns false if we aren't permitted to create the file
+    fn restore_special_file(&mut self, path: &Path, header: &Header) -> GenericResult<bool> {
+        let file_type = match header.entry_type() {
+            EntryType::Block => SFlag::S_IFBLK,
+            EntryType::Char => SFlag::S_IFCHR,
+            EntryType::Fifo => SFlag::S_IFIFO,
+            _ => unreachable!(),
+        };
+
+        let device = match (header.device_major()?, header.device_minor()?) {
+            (Some(major), Some(minor)) if file_type != SFlag::S_IFIFO => libc::makedev(major as _, minor as _),
+            _ => 0,
+        };
+
+        match nix::sys::stat::mknod(path, file_type, Mode::from_bits_truncate(0o600), device) {
+            Ok(()) => {
+                self.restored_files += 1;
+                Ok(true)
+            },
+            Err(Errno::EPERM) => {
+                debug!("Unable to create {:?}: {}.", path, Errno::EPERM);
+                Ok(false)
+            },
+            Err(err) => Err!("Unable to create {:?}: {}", path, err),
+        }
+    }
+
     fn restore_files(
-        &mut self, source_path: &Path, mut entry: Entry<Box<dyn Read>>, info: &RestoringFile,
-        restore_dir: &Path, is_target: bool,
+        &mut self, source_path: &Path, mut entry: Entry<Box<dyn Read>>, xattrs: Xattrs,
+        info: &RestoringFile, restore_dir: &Path, is_target: bool,
     ) -> EmptyResult {
         let paths = || info.paths.iter().map(|path| format!("{:?}", path)).join(", ");
         debug!("Restoring {}...", paths());
 
         let mut files = Vec::new();
+        let mut xattrs = Some(xattrs);
         let mut restore_metadata = None;
 
         let header = entry.header().clone();
@@ -217,7 +370,7 @@ impl Restorer {
 
             if is_target {
                 if path == source_path {
-                    let metadata = self.get_file_metadata(&header)?;
+                    let metadata = self.get_file_metadata(&header, xattrs.take().unwrap())?;
                     assert!(restore_metadata.replace((restore_path.clone(), metadata)).is_none());
                 } else {
                     self.pre_created_directories.extend(util::restore_directories(restore_dir, path)?);
@@ -231,6 +384,8 @@ impl Restorer {
                 .create_new(true).mode(0o600).custom_flags(libc::O_NOFOLLOW).write(true)
                 .open(&restore_path).map_err(|e| format!("Unable to create {:?}: {}", restore_path, e))?;
 
+            self.restored_files += 1;
+
             if let Some(ref data) = data {
                 file.write_all(data).map_err(|e| format!(
                     "Failed to restore {:?}: {}", path, e))?;
@@ -243,7 +398,7 @@ impl Restorer {
         }
 
         if data.is_none() {
-            let mut files = MultiWriter::new(files);
+            let mut files = MultiWriter::new(files, header.entry_type().is_gnu_sparse());
 
             io::copy(&mut reader, &mut files).map_err(|e| format!(
                 "Failed to restore {}: {}", paths(), e))?;
@@ -272,12 +427,63 @@ impl Restorer {
         Ok(())
     }
 
-    fn schedule_file_metadata_change(&mut self, path: PathBuf, header: &Header) -> EmptyResult {
-        self.scheduled_file_metadata.push((path, self.get_file_metadata(header)?));
+    // Chunks may precede their files in the archive, so chunked files are created in advance and then
+    // filled with their chunks while processing the backups.
+    fn create_chunked_files(&mut self, restore_dir: &Path) -> EmptyResult {
+        for (path, info) in &self.chunked_files {
+            self.pre_created_directories.extend(util::restore_directories(restore_dir, path)?);
+
+            let restore_path = get_restore_path(restore_dir, path)?;
+            let file = OpenOptions::new()
+                .create_new(true).mode(0o600).custom_flags(libc::O_NOFOLLOW).write(true)
+                .open(&restore_path).map_err(|e| format!("Unable to create {:?}: {}", restore_path, e))?;
+
+            // Zero chunks are skipped, leaving holes instead of them
+            file.set_len(info.size).map_err(|e| format!(
+                "Failed to restore {:?}: {}", path, e))?;
+
+            sys::close_file(file).map_err(|e| format!(
+                "Failed to restore {:?}: {}", path, e))?;
+
+            self.restored_files += 1;
+        }
+
+        Ok(())
+    }
+
+    fn restore_chunk(
+        &self, hash: &Hash, mut entry: Entry<Box<dyn Read>>, chunk: &RestoringChunk, restore_dir: &Path,
+    ) -> EmptyResult {
+        let data = util::read_chunk(&mut entry, hash, chunk.size)?;
+
+        // Leave a hole
+        if data.iter().all(|&byte| byte == 0) {
+            return Ok(());
+        }
+
+        for (path, offset) in &chunk.targets {
+            let restore_path = get_restore_path(restore_dir, path)?;
+
+            let file = OpenOptions::new()
+                .custom_flags(libc::O_NOFOLLOW).write(true)
+                .open(&restore_path).map_err(|e| format!("Unable to open {:?}: {}", restore_path, e))?;
+
+            file.write_all_at(&data, *offset).map_err(|e| format!(
+                "Failed to restore {:?}: {}", path, e))?;
+
+            sys::close_file(file).map_err(|e| format!(
+                "Failed to restore {:?}: {}", path, e))?;
+        }
+
+        Ok(())
+    }
+
+    fn schedule_file_metadata_change(&mut self, path: PathBuf, header: &Header, xattrs: Xattrs) -> EmptyResult {
+        self.scheduled_file_metadata.push((path, self.get_file_metadata(header, xattrs)?));
         Ok(())
     }
 
-    fn get_file_metadata(&self, header: &Header) -> GenericResult<FileMetadata> {
+    fn get_file_metadata(&self, header: &Header, xattrs: Xattrs) -> GenericResult<FileMetadata> {
         fn map_err<E: Display>(header: &Header, name: &str, err: E) -> String {
             format!("Got an invalid {}{} from archive: {}", name, match header.path() {
                 Ok(path) => format!(" for {:?}", path),
@@ -312,6 +518,6 @@ impl Restorer {
         let mtime = header.mtime()?.try_into().map_err(|e| map_err(
             header, "file modification time", e))?;
 
-        Ok(FileMetadata {owner, mode, mtime})
+        Ok(FileMetadata {owner, mode, xattrs, mtime})
     }
 }
\ No newline at end of file
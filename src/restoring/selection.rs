@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+
+use crate::backuping::PathFilter;
+use crate::core::GenericResult;
+use crate::util::sys;
+
+// Selects paths to restore: by path prefixes and filter rules which are matched against absolute
+// paths. Directories are additionally restored if they are needed as parents of selected paths.
+#[derive(Default)]
+pub struct RestoreSelection {
+    prefixes: Vec<PathBuf>,
+    filter: Option<PathFilter>,
+}
+
+impl RestoreSelection {
+    pub fn new(paths: &[PathBuf], filter: Option<&str>) -> GenericResult<RestoreSelection> {
+        let prefixes = paths.iter()
+            .map(|path| sys::normalize_path(path))
+            .collect::<GenericResult<Vec<_>>>()?;
+
+        let filter = filter.map(PathFilter::new).transpose()?;
+
+        Ok(RestoreSelection {prefixes, filter})
+    }
+
+    pub fn is_partial(&self) -> bool {
+        !self.prefixes.is_empty() || self.filter.is_some()
+    }
+
+    pub fn contains(&self, path: &Path) -> GenericResult<bool> {
+        if !self.prefixes.is_empty() && !self.prefixes.iter().any(|prefix| path.starts_with(prefix)) {
+            return Ok(false);
+        }
+
+        match self.filter {
+            Some(ref filter) => filter.check(path),
+            None => Ok(true),
+        }
+    }
+
+    pub fn is_prefix_parent(&self, path: &Path) -> bool {
+        self.prefixes.iter().any(|prefix| prefix != path && prefix.starts_with(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use super::*;
+
+    #[rstest(path, expected,
+        case("/etc", false),
+        case("/home/user", false),
+        case("/home/user/some-file", false),
+        case("/home/user/.ssh/config", true),
+        case("/home/user/.ssh/agent.socket", false),
+        case("/home/user/src/main.rs", true),
+        case("/home/user/src/main.o", false),
+        case("/home/user/src/project/main.o", false),
+    )]
+    fn selection(path: &str, expected: bool) {
+        let selection = RestoreSelection::new(
+            &[PathBuf::from("/home/user/./.ssh"), PathBuf::from("/home/user/src")],
+            Some("- **/*.socket\n- **/*.o"),
+        ).unwrap();
+
+        assert!(selection.is_partial());
+        assert_eq!(selection.contains(Path::new(path)).unwrap(), expected, "{}", path);
+    }
+
+    #[test]
+    fn prefix_parents() {
+        let selection = RestoreSelection::new(&[PathBuf::from("/home/user/src")], None).unwrap();
+
+        for path in ["/home", "/home/user"] {
+            assert!(selection.is_prefix_parent(Path::new(path)), "{}", path);
+        }
+
+        for path in ["/etc", "/home/user/src", "/home/user/src/project", "/home/other-user"] {
+            assert!(!selection.is_prefix_parent(Path::new(path)), "{}", path);
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use std::fs::DirBuilder;
-use std::io::{self, ErrorKind};
+use std::io::{self, ErrorKind, Read};
 use std::os::unix::fs::DirBuilderExt;
 use std::path::{Path, PathBuf, Component};
 
+use tar::Entry;
+
 use crate::core::{EmptyResult, GenericResult};
-use crate::util;
+use crate::util::{self, chunker, hash::Hash};
 
 pub fn get_file_path_from_tar_path<P: AsRef<Path>>(tar_path: P) -> GenericResult<PathBuf> {
     let tar_path = tar_path.as_ref();
@@ -28,6 +30,23 @@ pub fn get_file_path_from_tar_path<P: AsRef<Path>>(tar_path: P) -> GenericResult
     Ok(path)
 }
 
+// Reads chunk data from the archive entry checking its integrity
+pub fn read_chunk<R: Read>(entry: &mut Entry<R>, hash: &Hash, size: u64) -> GenericResult<Vec<u8>> {
+    if entry.size() != size {
+        return Err!("Got {} chunk of an unexpected size: {} vs {}", hash, entry.size(), size);
+    }
+
+    let mut data = Vec::with_capacity(size.try_into()?);
+    entry.read_to_end(&mut data).map_err(|e| format!(
+        "Error while reading {} chunk from archive: {}", hash, e))?;
+
+    if data.len() as u64 != size || chunker::get_chunk_hash(&data) != *hash {
+        return Err!("Got corrupted {} chunk", hash);
+    }
+
+    Ok(data)
+}
+
 pub fn get_restore_path<R, P>(restore_dir: R, file_path: P) -> GenericResult<PathBuf>
     where R: AsRef<Path>, P: AsRef<Path>
 {
@@ -1,23 +1,30 @@
 use std::collections::{HashMap, HashSet};
-use std::io::{Read, BufRead, BufReader};
+use std::io::{self, Read, BufRead, BufReader};
+use std::path::{Path, PathBuf};
 
-use log::error;
-use tar::Archive;
+use log::{debug, error};
+use serde_derive::Serialize;
+use tar::{Archive, Entry, EntryType};
 use zstd::stream::read::Decoder;
 
 use crate::core::GenericResult;
 use crate::providers::{ReadProvider, FileType};
-use crate::storage::metadata::MetadataReader;
+use crate::storage::metadata::{MetadataItem, MetadataReader};
+use crate::util::chunker;
+use crate::util::file_reader::FileReader;
 use crate::util::hash::Hash;
 
+#[derive(Serialize)]
 pub struct Backup {
     pub path: String,
     pub name: String,
+    #[serde(skip)]
     metadata_path: Option<String>,
     pub inner_stat: Option<BackupInnerStat>,
     pub outer_stat: Option<BackupOuterStat>,
 }
 
+#[derive(Serialize)]
 pub struct BackupInnerStat {
     pub extern_files: usize,
     pub unique_files: usize,
@@ -25,6 +32,7 @@ pub struct BackupInnerStat {
     pub unique_size: u64,
 }
 
+#[derive(Serialize)]
 pub struct BackupOuterStat {
     pub metadata_size: u64,
     pub data_size: u64,
@@ -87,16 +95,19 @@ impl Backup {
         let file = provider.open_file(&path).map_err(|e| format!(
             "Unable to open {:?}: {}", path, e))?;
 
+        // Allow any window size which may be configured for long distance matching
+        let mut decoder = Decoder::new(file)?;
+        decoder.window_log_max(31)?;
+
         let reader = Box::new(BufReader::with_capacity(
-            Decoder::<Box<dyn BufRead>>::recommended_output_size(),
-            Decoder::new(file)?,
-        ));
+            Decoder::<Box<dyn BufRead>>::recommended_output_size(), decoder));
 
         Ok(Archive::new(reader))
     }
 
     pub fn inspect(
         &mut self, provider: &dyn ReadProvider, available_hashes: &mut HashSet<Hash>,
+        available_chunks: &mut HashSet<Hash>,
     ) -> GenericResult<bool> {
         let mut recoverable = true;
         let mut stat = BackupInnerStat {
@@ -109,7 +120,33 @@ impl Backup {
         for file in self.read_metadata(provider)? {
             let file = file.map_err(|e| format!("Error while reading metadata file: {}", e))?;
 
-            if file.unique {
+            if let Some(chunks) = file.chunks {
+                let mut unique = false;
+
+                for chunk in chunks {
+                    if chunk.unique {
+                        unique = true;
+                        stat.unique_size += chunk.size;
+                        available_chunks.insert(chunk.hash);
+                    } else {
+                        stat.extern_size += chunk.size;
+
+                        if !available_chunks.contains(&chunk.hash) {
+                            error!(concat!(
+                                "{:?} backup{} is not recoverable: ",
+                                "unable to find {} chunk of {:?} file in the backup group."
+                            ), self.name, provider.clarification(), chunk.hash, file.path);
+                            recoverable = false;
+                        }
+                    }
+                }
+
+                if unique {
+                    stat.unique_files += 1;
+                } else {
+                    stat.extern_files += 1;
+                }
+            } else if file.unique {
                 stat.unique_files += 1;
                 stat.unique_size += file.size;
                 available_hashes.insert(file.hash);
@@ -135,4 +172,148 @@ impl Backup {
 
         Ok(has_files && recoverable)
     }
-}
\ No newline at end of file
+
+    pub fn verify(&self, provider: &dyn ReadProvider) -> GenericResult<bool> {
+        let mut ok = true;
+        let mut files = HashMap::new();
+        let mut chunks = HashMap::new();
+
+        for file in self.read_metadata(provider)? {
+            let file = file.map_err(|e| format!("Error while reading metadata file: {}", e))?;
+
+            for chunk in file.chunks.iter().flatten() {
+                if chunk.unique {
+                    chunks.insert(chunk.hash.clone(), chunk.size);
+                }
+            }
+
+            files.insert(PathBuf::from(&file.path), file);
+        }
+
+        let mut archive = self.read_data(provider)?;
+        let entries = archive.entries().map_err(|e| format!(
+            "Error while reading data archive: {}", e))?;
+
+        for entry in entries {
+            let mut entry = entry.map_err(|e| format!("Error while reading data archive: {}", e))?;
+            let entry_type = entry.header().entry_type();
+
+            if entry_type == EntryType::Continuous {
+                let path = entry.path()?.to_path_buf();
+                let hash = chunker::parse_chunk_path(&path)?;
+
+                let Some(size) = chunks.remove(&hash) else {
+                    error!("{:?} backup{} has an extra {:?} chunk in the data archive.",
+                           self.name, provider.clarification(), path);
+                    ok = false;
+                    continue;
+                };
+
+                ok &= self.verify_chunk(provider, &hash, size, &mut entry).map_err(|e| format!(
+                    "Error while reading {:?} from data archive: {}", path, e))?;
+                continue;
+            }
+
+            if !matches!(entry_type, EntryType::Regular | EntryType::GNUSparse | EntryType::Link) {
+                continue;
+            }
+
+            let path = Path::new("/").join(entry.path()?);
+            let file = match files.remove(&path) {
+                Some(file) => file,
+                None => {
+                    error!("{:?} backup{} has an extra {:?} file in the data archive.",
+                           self.name, provider.clarification(), path);
+                    ok = false;
+                    continue;
+                },
+            };
+
+            if file.hard_link != (entry_type == EntryType::Link) {
+                error!("{:?} backup{} has {:?} file of an unexpected type in the data archive.",
+                       self.name, provider.clarification(), path);
+                ok = false;
+                continue;
+            } else if file.hard_link {
+                continue;
+            }
+
+            ok &= self.verify_file(provider, &file, &mut entry).map_err(|e| format!(
+                "Error while reading {:?} from data archive: {}", path, e))?;
+        }
+
+        for path in files.keys() {
+            error!("{:?} backup{} has no {:?} file in the data archive.",
+                   self.name, provider.clarification(), path);
+            ok = false;
+        }
+
+        for hash in chunks.keys() {
+            error!("{:?} backup{} has no {} chunk in the data archive.",
+                   self.name, provider.clarification(), hash);
+            ok = false;
+        }
+
+        Ok(ok)
+    }
+
+    fn verify_chunk(
+        &self, provider: &dyn ReadProvider, hash: &Hash, size: u64, entry: &mut Entry<Box<dyn Read>>,
+    ) -> GenericResult<bool> {
+        if entry.size() != size {
+            error!("{:?} backup{} has {} chunk of an unexpected size: {} bytes instead of {}.",
+                   self.name, provider.clarification(), hash, entry.size(), size);
+            return Ok(false);
+        }
+
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+
+        if data.len() as u64 != size || chunker::get_chunk_hash(&data) != *hash {
+            error!("{:?} backup{} has corrupted {} chunk.", self.name, provider.clarification(), hash);
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    fn verify_file(
+        &self, provider: &dyn ReadProvider, file: &MetadataItem, entry: &mut Entry<Box<dyn Read>>,
+    ) -> GenericResult<bool> {
+        let data_size = entry.size();
+
+        if !file.unique || file.size == 0 {
+            if data_size != 0 {
+                error!("{:?} backup{} has data for {:?} file which is expected to be external.",
+                       self.name, provider.clarification(), file.path);
+                return Ok(false);
+            }
+            return Ok(true);
+        }
+
+        if data_size < file.size {
+            error!("{:?} backup{} has truncated {:?} file: {} bytes instead of {}.",
+                   self.name, provider.clarification(), file.path, data_size, file.size);
+            return Ok(false);
+        }
+
+        let mut reader = FileReader::new(entry, file.size);
+        io::copy(&mut reader, &mut io::sink())?;
+
+        let (bytes_read, hash) = reader.consume();
+        if bytes_read != file.size {
+            error!("{:?} backup{} has truncated {:?} file: {} bytes instead of {}.",
+                   self.name, provider.clarification(), file.path, bytes_read, file.size);
+            return Ok(false);
+        }
+
+        if hash != file.hash {
+            error!("{:?} backup{} has corrupted {:?} file: got {} hash instead of {}.",
+                   self.name, provider.clarification(), file.path, hash, file.hash);
+            return Ok(false);
+        }
+
+        debug!("{:?}: OK.", file.path);
+        Ok(true)
+    }
+}
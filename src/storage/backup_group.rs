@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 
 use log::{error, warn};
+use serde_derive::Serialize;
 
 use crate::core::GenericResult;
 use crate::providers::{ReadProvider, FileType};
@@ -8,9 +9,11 @@ use crate::providers::{ReadProvider, FileType};
 use super::backup::Backup;
 use super::traits::BackupTraits;
 
+#[derive(Serialize)]
 pub struct BackupGroup {
     pub name: String,
     pub backups: Vec<Backup>,
+    #[serde(skip)]
     pub temporary_backups: Vec<Backup>,
 }
 
@@ -53,6 +56,17 @@ impl BackupGroup {
                 "Unable to list {:?} backup group: {}", group_path, e))?;
             ok &= group_ok;
 
+            // Checked only on full listing, because a single group may be stored partially (for
+            // example, when it's downloaded from cloud for restoring)
+            if let Some(backup) = group.backups.first() &&
+                cfg!(not(test)) && backup.name.split('-').next().unwrap() != group.name {
+                error!(concat!(
+                    "Suspicious first backup {:?} in {:?} group{}: ",
+                    "possibly corrupted backup group."
+                ), backup.name, group.name, provider.clarification());
+                ok = false;
+            }
+
             backup_groups.push(group);
         }
 
@@ -61,7 +75,6 @@ impl BackupGroup {
 
     pub fn read(provider: &dyn ReadProvider, name: &str, path: &str, strict: bool) -> GenericResult<(BackupGroup, bool)> {
         let mut ok = true;
-        let mut first_backup = true;
 
         let mut group = BackupGroup::new(name);
         let traits = BackupTraits::get_for(provider.type_());
@@ -100,18 +113,6 @@ impl BackupGroup {
                 continue
             }
 
-            if first_backup {
-                first_backup = false;
-
-                if cfg!(not(test)) && backup_name.split('-').next().unwrap() != group.name {
-                    error!(concat!(
-                        "Suspicious first backup {:?} in {:?} group{}: ",
-                        "possibly corrupted backup group."
-                    ), backup_name, group.name, provider.clarification());
-                    ok = false;
-                }
-            }
-
             let backup = match Backup::read(
                 provider, backup_name, &backup_path,
                 file.type_ != FileType::Directory
@@ -136,9 +137,10 @@ impl BackupGroup {
     pub fn inspect(&mut self, provider: &dyn ReadProvider) -> bool {
         let mut ok = true;
         let mut available_hashes = HashSet::new();
+        let mut available_chunks = HashSet::new();
 
         for backup in &mut self.backups {
-            match backup.inspect(provider, &mut available_hashes) {
+            match backup.inspect(provider, &mut available_hashes, &mut available_chunks) {
                 Ok(recoverable) => ok &= recoverable,
                 Err(err) => {
                     error!("{:?} backup{} validation error: {}.",
@@ -0,0 +1,118 @@
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::process::{Command, Stdio, Child, ChildStdout};
+use std::thread::JoinHandle;
+
+use libc::pid_t;
+use log::debug;
+
+use crate::core::{EmptyResult, GenericResult};
+use crate::util;
+
+use super::encryptor::{create_passphrase_pipe, terminate_gpg};
+
+pub struct Decryptor {
+    pid: pid_t,
+    gpg: Option<Child>,
+    stdout: ChildStdout,
+    stdin_writer: Option<JoinHandle<EmptyResult>>,
+    stderr_reader: Option<JoinHandle<GenericResult<String>>>,
+}
+
+impl Decryptor {
+    pub fn new(encryption_passphrase: &str, mut reader: Box<dyn Read + Send>) -> GenericResult<Decryptor> {
+        let (passphrase_read_fd, mut passphrase_write_fd) = create_passphrase_pipe()
+            .map_err(|e| format!("Unable to create a pipe: {}", e))?;
+
+        debug!("Spawning a gpg process to handle data decryption...");
+
+        let mut gpg = Command::new("gpg")
+            .arg("--batch").arg("--quiet").arg("--decrypt")
+            .arg("--passphrase-fd").arg(passphrase_read_fd.as_raw_fd().to_string())
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+            .spawn().map_err(|e| format!("Unable to spawn a gpg process: {}", e))?;
+        drop(passphrase_read_fd);
+
+        let mut stdin = gpg.stdin.take().unwrap();
+        let mut stderr = gpg.stderr.take().unwrap();
+
+        // From this point the process will be terminated on drop in case of error
+        let mut decryptor = Decryptor {
+            pid: gpg.id() as pid_t,
+            stdout: gpg.stdout.take().unwrap(),
+            gpg: Some(gpg),
+            stdin_writer: None,
+            stderr_reader: None,
+        };
+
+        decryptor.stderr_reader = Some(util::sys::spawn_thread("gpg stderr reader", move || {
+            let mut error = String::new();
+            stderr.read_to_string(&mut error).map_err(|e| format!(
+                "gpg stderr reading error: {}", e))?;
+            Ok(error)
+        })?);
+
+        passphrase_write_fd.write_all(encryption_passphrase.as_bytes())
+            .and_then(|_| passphrase_write_fd.flush())
+            .map_err(|e| format!("Failed to pass encryption passphrase to gpg: {}", e))?;
+        drop(passphrase_write_fd);
+
+        decryptor.stdin_writer = Some(util::sys::spawn_thread("gpg stdin writer", move || -> EmptyResult {
+            io::copy(&mut reader, &mut stdin).map_err(|e| format!(
+                "Failed to pass encrypted data to gpg: {}", e))?;
+            Ok(())
+        })?);
+
+        Ok(decryptor)
+    }
+
+    fn finish(&mut self) -> EmptyResult {
+        let mut gpg = match self.gpg.take() {
+            Some(gpg) => gpg,
+            None => return Ok(()),
+        };
+
+        debug!("Waiting for gpg process termination...");
+
+        let writer_result = util::sys::join_thread(self.stdin_writer.take().unwrap());
+        let stderr_result = util::sys::join_thread(self.stderr_reader.take().unwrap());
+        let status = gpg.wait().map_err(|e| format!(
+            "Failed to wait() a child gpg process: {}", e))?;
+
+        // The encrypted data reading error is the root cause of all other errors if any
+        writer_result?;
+        let error = stderr_result?;
+
+        if !status.success() {
+            let error = error.trim_end();
+            if error.is_empty() {
+                return Err!("gpg process has terminated with an error exit code");
+            }
+            return Err!("gpg error: {}", error);
+        }
+
+        debug!("gpg process has end its work with successful exit code.");
+        Ok(())
+    }
+}
+
+impl Read for Decryptor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.stdout.read(buf)?;
+
+        if size == 0 && !buf.is_empty() {
+            self.finish().map_err(|e| io::Error::other(e.to_string()))?;
+        }
+
+        Ok(size)
+    }
+}
+
+impl Drop for Decryptor {
+    fn drop(&mut self) {
+        if self.gpg.take().is_some() {
+            // The threads will be terminated by closed pipes
+            terminate_gpg(self.pid);
+        }
+    }
+}
@@ -151,7 +151,7 @@ impl io::Write for Encryptor {
 }
 
 #[cfg(not(target_os = "macos"))]
-fn create_passphrase_pipe() -> nix::Result<(File, File)> {
+pub fn create_passphrase_pipe() -> nix::Result<(File, File)> {
     let (read_fd, write_fd) = unistd::pipe2(fcntl::OFlag::O_CLOEXEC).map(|(read_fd, write_fd)| {
         (File::from(read_fd), File::from(write_fd))
     })?;
@@ -162,7 +162,7 @@ fn create_passphrase_pipe() -> nix::Result<(File, File)> {
 }
 
 #[cfg(target_os = "macos")]
-fn create_passphrase_pipe() -> nix::Result<(File, File)> {
+pub fn create_passphrase_pipe() -> nix::Result<(File, File)> {
     let (read_fd, write_fd) = unistd::pipe().map(|(read_fd, write_fd)| {
         (File::from(read_fd), File::from(write_fd))
     })?;
@@ -231,7 +231,7 @@ fn read_data(mut stdout: BufReader<ChildStdout>, mut hasher: Box<dyn Hasher>, tx
     }
 }
 
-fn terminate_gpg(pid: pid_t) {
+pub fn terminate_gpg(pid: pid_t) {
     let termination_timeout = time::Duration::from_secs(3);
     if let Err(err) = util::sys::terminate_process("a child gpg process", pid, termination_timeout) {
         error!("{}.", err)
@@ -1,5 +1,6 @@
 use std::fs;
 use std::io::{self, Read, BufRead, BufReader, Lines, Write, BufWriter};
+use std::iter::Peekable;
 use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 
@@ -8,40 +9,99 @@ use zstd::stream::{read::Decoder, write::Encoder};
 use crate::core::{EmptyResult, GenericResult};
 use crate::util::hash::Hash;
 
+// Backup metadata is a zstd-compressed text file with a line per file:
+//
+//   {unique|extern|link|chunked} {hash} {device}:{inode}:{mtime_nsec} {size} {path}
+//
+// Unique files have their data in the backup's data archive, extern files refer to data stored in other
+// backups of the group and hard links refer to a previous file of the backup. Data of chunked files is
+// split into chunks which are listed in the following lines in the file order:
+//
+//   chunk {unique|extern} {hash} {size}
+//
+// Unique chunks are stored in the data archive as `vsb-chunks/{hash}` contiguous file entries. Hard links to
+// chunked files are followed by the chunks of their target, which are all extern.
+//
+// Chunk boundaries are content-defined (see `util::chunker`): with the configured average chunk size N
+// chunks are at least N/4 bytes (except the last one), N on average and at most 4*N bytes. Chunks are
+// deduplicated across backups of the group, so these limits and the boundary hash are a part of the format.
 pub struct MetadataItem {
     pub path: String,
     pub size: u64,
     pub hash: Hash,
     pub unique: bool,
+    // Hard link to a previous file of the backup with the same device and inode (has no own data)
+    pub hard_link: bool,
     pub fingerprint: Fingerprint,
+    // Data chunks of a file which is stored by chunks
+    pub chunks: Option<Vec<Chunk>>,
+}
+
+#[derive(Clone)]
+pub struct Chunk {
+    pub hash: Hash,
+    pub size: u64,
+    pub unique: bool,
 }
 
 impl MetadataItem {
     pub fn new(path: &Path, size: u64, hash: Hash, fingerprint: Fingerprint, unique: bool) -> GenericResult<MetadataItem> {
         let path = validate_path(path)?.to_owned();
-        Ok(MetadataItem {path, size, hash, unique, fingerprint})
+        Ok(MetadataItem {path, size, hash, unique, hard_link: false, fingerprint, chunks: None})
     }
 
-    fn encode(&self, writer: &mut dyn Write) -> EmptyResult {
-        let status = match self.unique {
-            true => "unique",
-            false => "extern",
-        };
+    pub fn new_hard_link(
+        path: &Path, size: u64, hash: Hash, fingerprint: Fingerprint, chunks: Option<Vec<Chunk>>,
+    ) -> GenericResult<MetadataItem> {
+        let path = validate_path(path)?.to_owned();
+        Ok(MetadataItem {path, size, hash, unique: false, hard_link: true, fingerprint, chunks})
+    }
 
-        Ok(writeln!(
+    pub fn new_chunked(
+        path: &Path, size: u64, hash: Hash, fingerprint: Fingerprint, chunks: Vec<Chunk>,
+    ) -> GenericResult<MetadataItem> {
+        let path = validate_path(path)?.to_owned();
+        Ok(MetadataItem {path, size, hash, unique: false, hard_link: false, fingerprint, chunks: Some(chunks)})
+    }
+
+    pub fn status(&self) -> &'static str {
+        if self.hard_link {
+            "link"
+        } else if self.chunks.is_some() {
+            "chunked"
+        } else if self.unique {
+            "unique"
+        } else {
+            "extern"
+        }
+    }
+
+    fn encode(&self, writer: &mut dyn Write) -> EmptyResult {
+        writeln!(
             writer, "{status} {hash} {fingerprint} {size} {path}",
-            status=status, hash=self.hash, fingerprint=self.fingerprint.encode(), size=self.size,
+            status=self.status(), hash=self.hash, fingerprint=self.fingerprint.encode(), size=self.size,
             path=self.path,
-        )?)
+        )?;
+
+        for chunk in self.chunks.iter().flatten() {
+            writeln!(
+                writer, "chunk {status} {hash} {size}",
+                status=if chunk.unique {"unique"} else {"extern"}, hash=chunk.hash, size=chunk.size,
+            )?;
+        }
+
+        Ok(())
     }
 
     fn decode(line: &str) -> GenericResult<MetadataItem> {
         let mut parts = line.splitn(5, ' ');
         let error = || format!("Unexpected format: {:?}", line);
 
-        let unique = parts.next().and_then(|status| match status {
-            "extern" => Some(false),
-            "unique" => Some(true),
+        let (unique, hard_link, chunked) = parts.next().and_then(|status| match status {
+            "extern" => Some((false, false, false)),
+            "unique" => Some((true, false, false)),
+            "link" => Some((false, true, false)),
+            "chunked" => Some((false, false, true)),
             _ => None,
         }).ok_or_else(error)?;
 
@@ -51,7 +111,39 @@ impl MetadataItem {
         let size = parts.next().and_then(|v| v.parse::<u64>().ok()).ok_or_else(error)?;
         let path = parts.next().ok_or_else(error)?.to_owned();
 
-        Ok(MetadataItem {path, size, hash, unique, fingerprint})
+        let chunks = if chunked {
+            Some(Vec::new())
+        } else {
+            None
+        };
+
+        Ok(MetadataItem {path, size, hash, unique, hard_link, fingerprint, chunks})
+    }
+}
+
+impl Chunk {
+    fn decode(line: &str) -> GenericResult<Chunk> {
+        let mut parts = line.split(' ');
+        let error = || format!("Unexpected format: {:?}", line);
+
+        if parts.next() != Some("chunk") {
+            return Err(error().into());
+        }
+
+        let unique = parts.next().and_then(|status| match status {
+            "extern" => Some(false),
+            "unique" => Some(true),
+            _ => None,
+        }).ok_or_else(error)?;
+
+        let hash = parts.next().ok_or_else(error)?.try_into()?;
+        let size = parts.next().and_then(|v| v.parse::<u64>().ok()).ok_or_else(error)?;
+
+        if parts.next().is_some() {
+            return Err(error().into());
+        }
+
+        Ok(Chunk {hash, size, unique})
     }
 }
 
@@ -74,6 +166,11 @@ impl Fingerprint {
         }
     }
 
+    // Identifies the file on the filesystem, so hard links have the same ID
+    pub fn file_id(&self) -> (u64, u64) {
+        (self.device, self.inode)
+    }
+
     fn encode(&self) -> String {
         format!(
             "{device}:{inode}:{mtime}",
@@ -109,7 +206,7 @@ pub fn validate_path(path: &Path) -> GenericResult<&str> {
 }
 
 pub struct MetadataReader {
-    lines: Lines<Box<dyn BufRead>>,
+    lines: Peekable<Lines<Box<dyn BufRead>>>,
 }
 
 impl MetadataReader {
@@ -118,7 +215,28 @@ impl MetadataReader {
             Decoder::<Box<dyn BufRead>>::recommended_output_size(),
             Decoder::new(reader).unwrap(),
         ));
-        MetadataReader {lines: reader.lines()}
+        MetadataReader {lines: reader.lines().peekable()}
+    }
+
+    fn read_chunks(&mut self, file: &mut MetadataItem) -> EmptyResult {
+        if file.hard_link && let Some(Ok(line)) = self.lines.peek() && line.starts_with("chunk ") {
+            file.chunks = Some(Vec::new());
+        }
+
+        let Some(chunks) = file.chunks.as_mut() else {
+            return Ok(());
+        };
+
+        while let Some(Ok(line)) = self.lines.peek() && line.starts_with("chunk ") {
+            chunks.push(Chunk::decode(&self.lines.next().unwrap()?)?);
+        }
+
+        let size: u64 = chunks.iter().map(|chunk| chunk.size).sum();
+        if size != file.size {
+            return Err!("Chunks of {:?} don't match its size", file.path);
+        }
+
+        Ok(())
     }
 }
 
@@ -126,7 +244,11 @@ impl Iterator for MetadataReader {
     type Item = GenericResult<MetadataItem>;
 
     fn next(&mut self) -> Option<GenericResult<MetadataItem>> {
-        self.lines.next().map(|line| MetadataItem::decode(&line?))
+        self.lines.next().map(|line| {
+            let mut file = MetadataItem::decode(&line?)?;
+            self.read_chunks(&mut file)?;
+            Ok(file)
+        })
     }
 }
 
@@ -135,11 +257,11 @@ pub struct MetadataWriter<W: Write> {
 }
 
 impl<W: Write> MetadataWriter<W> {
-    pub fn new(writer: W) -> MetadataWriter<W> {
+    pub fn new(writer: W, level: i32) -> MetadataWriter<W> {
         MetadataWriter {
             writer: BufWriter::with_capacity(
                 Encoder::<W>::recommended_input_size(),
-                Encoder::new(writer, 2).unwrap(),
+                Encoder::new(writer, level).unwrap(),
             )
         }
     }
@@ -1,10 +1,15 @@
 mod adapters;
 mod backup;
 mod backup_group;
+mod decryptor;
 mod encryptor;
 pub mod metadata;
 mod traits;
 
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Component, Path};
 use std::rc::Rc;
 use std::time::SystemTime;
 
@@ -13,10 +18,12 @@ use log::{info, warn, error};
 use rayon::prelude::*;
 
 use crate::core::{EmptyResult, GenericResult};
-use crate::providers::{FileType, ReadProvider, WriteProvider, UploadProvider};
+use crate::providers::{FileType, ProviderType, ReadProvider, WriteProvider, UploadProvider};
+use crate::providers::filesystem::Filesystem;
 use crate::util::{self, stream_splitter};
 
 use self::adapters::{AbstractProvider, ReadOnlyProviderAdapter, ReadWriteProviderAdapter, UploadProviderAdapter};
+use self::decryptor::Decryptor;
 use self::encryptor::Encryptor;
 
 pub use self::backup::Backup;
@@ -52,6 +59,37 @@ impl Storage {
         })
     }
 
+    // Opens local storage by a path to one of its backups. Returns the storage, group name and
+    // backup name.
+    pub fn open_local_backup(backup_path: &Path) -> GenericResult<(StorageRc, String, String)> {
+        let backup_path = backup_path.canonicalize().map_err(|e| format!(
+            "Invalid backup path: {}", e))?;
+
+        let (backup_root, group_name, backup_name) = {
+            let backup_name = backup_path.file_name().and_then(|name| name.to_str());
+            let group_path = backup_path.parent();
+            let group_name = group_path.and_then(|path| path.file_name()).and_then(|name| name.to_str());
+            let backup_root = group_path.and_then(|path| path.parent()).and_then(|name| name.to_str());
+
+            match (backup_root, group_name, backup_name) {
+                (Some(root), Some(group_name), Some(backup_name)) => (root, group_name, backup_name),
+                _ => return Err!("Invalid backup path"),
+            }
+        };
+
+        let storage = Storage::new_read_only(Filesystem::new(), backup_root);
+        let backup_traits = storage.backup_traits();
+
+        if
+            !backup_traits.group_name_regex.is_match(group_name) ||
+            !backup_traits.name_regex.is_match(backup_name)
+        {
+            return Err!("{:?} doesn't look like backup path", backup_path)
+        }
+
+        Ok((storage, group_name.to_owned(), backup_name.to_owned()))
+    }
+
     pub fn name(&self) -> &str {
         self.provider.read().name()
     }
@@ -93,6 +131,12 @@ impl Storage {
         Ok(group)
     }
 
+    pub fn get_backup(&self, group_name: &str, backup_name: &str) -> GenericResult<Backup> {
+        let group = self.get_backup_group(group_name, true)?;
+        group.backups.into_iter().find(|backup| backup.name == backup_name).ok_or_else(|| format!(
+            "{:?} backup doesn't exist", backup_name).into())
+    }
+
     pub fn create_backup(&self, max_backups: usize) -> GenericResult<(BackupGroup, Backup)> {
         let provider = self.provider.write()?;
 
@@ -179,6 +223,18 @@ impl Storage {
         Ok(())
     }
 
+    // Downloads the backup from the cloud storage and unpacks it to the specified local backup group
+    pub fn download_backup(&self, group_name: &str, backup_name: &str, encryption_passphrase: &str,
+                           local_group_path: &Path) -> EmptyResult {
+        let provider = self.provider.read();
+        let path = self.get_backup_path(group_name, backup_name, false);
+
+        let file = provider.open_file(&path).map_err(|e| format!(
+            "Unable to open {:?}{}: {}", path, provider.clarification(), e))?;
+
+        extract_backup(file, backup_name, encryption_passphrase, local_group_path)
+    }
+
     pub fn delete_backup_group(&self, group_name: &str) -> EmptyResult {
         let group_path = self.get_backup_group_path(group_name);
         self.provider.write()?.delete(&group_path)
@@ -224,3 +280,129 @@ fn archive_backup(backup_name: &str, backup_path: &str, encryptor: Encryptor) ->
 
     archive.into_inner().unwrap().finish(None)
 }
+
+// Decrypts the backup archive (as it's stored in the cloud) and unpacks it to the specified local
+// backup group
+pub fn extract_backup(
+    archive: Box<dyn io::Read + Send>, backup_name: &str, encryption_passphrase: &str, local_group_path: &Path,
+) -> EmptyResult {
+    let local_traits = BackupTraits::get_for(ProviderType::Local);
+    let temp_path = local_group_path.join(local_traits.temporary_prefix.to_owned() + backup_name);
+    let local_path = local_group_path.join(backup_name);
+
+    if fs::symlink_metadata(&local_path).is_ok() {
+        return Err!("{:?} already exists", local_path);
+    }
+
+    let decryptor = Decryptor::new(encryption_passphrase, archive)?;
+
+    DirBuilder::new().mode(0o700).create(&temp_path).map_err(|e| format!(
+        "Unable to create {:?}: {}", temp_path, e))?;
+
+    if let Err(err) = unpack_backup(backup_name, decryptor, &temp_path).and_then(|_| {
+        for name in [Backup::DATA_NAME, Backup::METADATA_NAME] {
+            if !temp_path.join(name).exists() {
+                return Err!("The backup archive doesn't contain {} file", name);
+            }
+        }
+        Ok(fs::rename(&temp_path, &local_path)?)
+    }) {
+        if let Err(err) = fs::remove_dir_all(&temp_path) {
+            error!("Failed to delete {:?}: {}.", temp_path, err);
+        }
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn unpack_backup(backup_name: &str, decryptor: Decryptor, path: &Path) -> EmptyResult {
+    let mut archive = tar::Archive::new(decryptor);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        let entry_type = entry.header().entry_type();
+
+        let mut components = entry_path.components();
+        if components.next() != Some(Component::Normal(backup_name.as_ref())) {
+            return Err!("The backup archive contains an unexpected file: {:?}", entry_path);
+        }
+
+        match (components.next(), components.next()) {
+            (None, _) if entry_type.is_dir() => {},
+            (Some(Component::Normal(name)), None) if entry_type.is_file() && (
+                name == Backup::DATA_NAME || name == Backup::METADATA_NAME
+            ) => {
+                let file_path = path.join(name);
+                entry.unpack(&file_path).map_err(|e| format!(
+                    "Unable to unpack {:?}: {}", file_path, e))?;
+            },
+            _ => return Err!("The backup archive contains an unexpected file: {:?}", entry_path),
+        }
+    }
+
+    // Read the data till the end to get gpg exit status
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use assert_fs::fixture::TempDir;
+
+    use crate::util::hash::Md5;
+    use crate::util::stream_splitter::Data;
+
+    use super::*;
+
+    #[test]
+    fn extraction() -> EmptyResult {
+        let temp_dir = TempDir::new()?;
+        let backup_name = "2026.10.17-01:02:03";
+
+        let backup_path = temp_dir.join("backup");
+        fs::create_dir(&backup_path)?;
+        fs::write(backup_path.join(Backup::DATA_NAME), "data")?;
+        fs::write(backup_path.join(Backup::METADATA_NAME), "metadata")?;
+
+        let (encryptor, data_stream) = Encryptor::new("passphrase", Box::new(Md5::new()))?;
+        let receiver = util::sys::spawn_thread("receiver", move || -> GenericResult<Vec<u8>> {
+            let mut encrypted_data = Vec::new();
+
+            for data in data_stream.iter() {
+                match data? {
+                    Data::Payload(payload) => encrypted_data.extend_from_slice(&payload),
+                    Data::EofWithChecksum(_) => return Ok(encrypted_data),
+                }
+            }
+
+            Err!("The data stream has been closed unexpectedly")
+        })?;
+
+        archive_backup(backup_name, backup_path.to_str().unwrap(), encryptor)?;
+        let encrypted_data = util::sys::join_thread(receiver)?;
+
+        let group_path = temp_dir.join("group");
+        fs::create_dir(&group_path)?;
+
+        let archive = Box::new(Cursor::new(encrypted_data.clone()));
+        assert!(extract_backup(archive, backup_name, "invalid", &group_path).is_err());
+        assert_eq!(fs::read_dir(&group_path)?.count(), 0);
+
+        let archive = Box::new(Cursor::new(encrypted_data.clone()));
+        extract_backup(archive, backup_name, "passphrase", &group_path)?;
+
+        let extracted_path = group_path.join(backup_name);
+        assert_eq!(fs::read_to_string(extracted_path.join(Backup::DATA_NAME))?, "data");
+        assert_eq!(fs::read_to_string(extracted_path.join(Backup::METADATA_NAME))?, "metadata");
+
+        let archive = Box::new(Cursor::new(encrypted_data));
+        assert!(extract_backup(archive, backup_name, "passphrase", &group_path).is_err());
+
+        Ok(())
+    }
+}
@@ -1,8 +1,8 @@
 use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::fs::{self, Permissions};
-use std::io::ErrorKind;
-use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::fs::{self, File, Permissions};
+use std::io::{ErrorKind, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileExt, FileTypeExt, MetadataExt, PermissionsExt};
 use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 use std::time::SystemTime;
@@ -15,16 +15,22 @@ use itertools::Itertools;
 use log::info;
 use maplit::hashset;
 use sha2::Sha512;
+use tar::EntryType;
 use nix::sys::stat::Mode;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 
 use crate::backuping::{self, PathFilter};
-use crate::config::{BackupSpecConfig, BackupConfig, BackupItemConfig};
+use crate::config::{BackupSpecConfig, BackupConfig, BackupItemConfig, ChunkingConfig, CompressionConfig};
 use crate::core::{GenericResult, EmptyResult};
+use crate::inspecting;
 use crate::providers::{ReadProvider, filesystem::Filesystem};
-use crate::restoring;
+use crate::restoring::{self, RestoreSelection};
 use crate::storage::{Backup, Storage};
 use crate::storage::metadata::{Fingerprint, MetadataItem};
 use crate::util::hash::Hash;
+use crate::util::output::OutputFormat;
+use crate::util::sparse;
+use crate::util::xattr::{self, Xattrs};
 
 #[test]
 fn backup() -> EmptyResult {
@@ -72,6 +78,31 @@ fn backup() -> EmptyResult {
         })?;
     }
 
+    // Check hard links preserving (including links across backup items)
+    let hard_link_paths = [user_path.join("hard-link"), other_user_path.join("hard-link")];
+    for path in &hard_link_paths {
+        if let Err(err) = fs::remove_file(path) && err.kind() != ErrorKind::NotFound {
+            return Err(err.into());
+        }
+        fs::hard_link(user_path.join("non-empty"), path)?;
+    }
+
+    // Check special files backup (the FIFO is deleted after the backups, because git can't diff it)
+    let fifo_path = user_path.join("fifo");
+    if let Err(err) = fs::remove_file(&fifo_path) && err.kind() != ErrorKind::NotFound {
+        return Err(err.into());
+    }
+    nix::unistd::mkfifo(&fifo_path, Mode::from_bits_truncate(0o640))?;
+
+    // Check sparse files backup
+    let sparse_path = user_path.join("sparse");
+    {
+        let mut file = File::create(&sparse_path)?;
+        file.set_len(1024 * 1024)?;
+        file.seek(SeekFrom::Start(100_000))?;
+        file.write_all(b"sparse file data")?;
+    }
+
     let temp_dir = TempDir::new()?;
     let backup_root_path = temp_dir.join("backups");
     fs::create_dir(&backup_root_path)?;
@@ -86,7 +117,8 @@ fn backup() -> EmptyResult {
         backup: Some(BackupConfig {
             items: vec![BackupItemConfig {
                 path: root_path.join("etc").to_str().unwrap().to_owned(),
-                filter: PathFilter::default(), before: None, after: None,
+                filter: PathFilter::default(), before: None, after: None, compress: true,
+                one_file_system: false, filesystems: Vec::new(),
             }, BackupItemConfig {
                 path: user_path.to_str().unwrap().to_owned(),
                 filter: PathFilter::new(indoc!("
@@ -94,7 +126,8 @@ fn backup() -> EmptyResult {
                     + partially-excluded/included-*
                     - partially-excluded/*
                 "))?,
-                before: None, after: None,
+                before: None, after: None, compress: true,
+                one_file_system: true, filesystems: Vec::new(),
             }, BackupItemConfig {
                 path: other_user_path.to_str().unwrap().to_owned(),
                 filter: PathFilter::default(),
@@ -102,12 +135,16 @@ fn backup() -> EmptyResult {
                     "uuidgen > {before:?} && cp -a {before:?} {after:?}",
                     before=before_path, after=after_path)),
                 after: Some(format!("uuidgen > {:?}", after_path)),
+                compress: false, one_file_system: false, filesystems: Vec::new(),
             }, BackupItemConfig {
                 path: var_path.join("data").to_str().unwrap().to_owned(),
-                filter: PathFilter::default(), before: None, after: None,
+                filter: PathFilter::default(), before: None, after: None, compress: true,
+                one_file_system: false, filesystems: Vec::new(),
             }],
             max_backup_groups,
             max_backups_per_group,
+            compression: CompressionConfig {long_window: Some(27), ..Default::default()},
+            chunking: None,
         }),
         upload: None
     };
@@ -117,16 +154,25 @@ fn backup() -> EmptyResult {
 
     // Check permissions preserving for directories
     let permissions_dir_path = user_path.join("permissions");
+    #[allow(clippy::useless_conversion)] // mode_t is u16 on macOS
     fs::set_permissions(&permissions_dir_path, Permissions::from_mode((
         Mode::from_bits(0o511).unwrap() | Mode::S_ISUID | Mode::S_ISGID | Mode::S_ISVTX
     ).bits().into()))?;
 
     // Check permissions preserving for files
     let permissions_file_path = permissions_dir_path.join("permissions");
+    #[allow(clippy::useless_conversion)] // mode_t is u16 on macOS
     fs::set_permissions(permissions_file_path, Permissions::from_mode((
         Mode::from_bits(0o404).unwrap() | Mode::S_ISUID | Mode::S_ISGID | Mode::S_ISVTX
     ).bits().into()))?;
 
+    // Check extended attributes preserving for directories and extern files
+    let xattrs: Xattrs = vec![("user.vsb-test".to_owned(), b"value".to_vec())];
+    let xattrs_paths = [permissions_dir_path.clone(), user_path.join("same-contents-2")];
+    for path in &xattrs_paths {
+        xattr::set(path, &xattrs)?;
+    }
+
     let mut mutable_files_states = Vec::new();
     let mutable_file_path = user_path.join("mutable");
     let same_mutable_orig_file_path = user_path.join("same-mutable-1/nested/same-mutable");
@@ -172,7 +218,15 @@ fn backup() -> EmptyResult {
             })?,
         ];
 
-        assert!(backuping::backup(&config)?);
+        // Dry run mustn't change the storage
+        let count_backups = || -> GenericResult<usize> {
+            Ok(storage.get_backup_groups(true)?.0.iter().map(|group| group.backups.len()).sum())
+        };
+        let backups = count_backups()?;
+        assert!(backuping::backup(&config, true)?.ok);
+        assert_eq!(count_backups()?, backups);
+
+        assert!(backuping::backup(&config, false)?.ok);
 
         // `after` contents was the same as `before` during backup, but must be different now
         let before_state = FileState::acquire(&before_path)?;
@@ -192,6 +246,7 @@ fn backup() -> EmptyResult {
         assert_eq!(group.backups.len(), pass % max_backups_per_group + 1);
 
         let backup = group.backups.last().unwrap();
+        assert!(backup.verify(storage.provider.read())?);
         let files = read_metadata(storage.provider.read(), backup)?;
 
         for path in &all_excluded_files {
@@ -214,6 +269,9 @@ fn backup() -> EmptyResult {
             user_path.join("other-empty"),
             user_path.join("same-contents-2"),
             same_mutable_extern_file_path.clone(),
+            // Hard links to user/hard-link
+            user_path.join("non-empty"),
+            other_user_path.join("hard-link"),
         };
 
         for (path, file) in files {
@@ -248,6 +306,7 @@ fn backup() -> EmptyResult {
     }
 
     let var_time = fs::metadata(&var_path)?.modified()?;
+    let user_time = fs::metadata(&user_path)?.modified()?;
     let partially_excluded_time = fs::metadata(&partially_excluded_path)?.modified()?;
 
     for path in &all_excluded_paths {
@@ -257,8 +316,10 @@ fn backup() -> EmptyResult {
             fs::remove_file(path)?;
         }
     }
+    fs::remove_file(&fifo_path)?;
 
     filetime::set_file_mtime(&var_path, FileTime::from_system_time(var_time))?;
+    filetime::set_file_mtime(&user_path, FileTime::from_system_time(user_time))?;
     filetime::set_file_mtime(&partially_excluded_path, FileTime::from_system_time(partially_excluded_time))?;
 
     let (groups, ok) = storage.get_backup_groups(true)?;
@@ -277,36 +338,56 @@ fn backup() -> EmptyResult {
             info!("Restoring #{} pass ({})...", restore_pass, backup.name);
 
             let restore_dir = temp_dir.join("restore");
-            assert!(restoring::restore(Path::new(&backup.path), &restore_dir)?);
+            assert!(restoring::restore(Path::new(&backup.path), &restore_dir, RestoreSelection::default())?.ok);
 
             for file_state in &mutable_files_states[restore_pass] {
                 file_state.restore()?;
             }
 
             let restored_root_path = get_restore_path(&restore_dir, &root_path);
+            check_fifo(&fifo_path, &restore_dir)?;
+            compare_trees(&root_path, &restored_root_path)?;
+            check_xattrs(&xattrs_paths, &restore_dir, &xattrs)?;
+            assert!(sparse::is_sparse(&fs::metadata(get_restore_path(&restore_dir, &sparse_path))?));
 
-            shell(&formatdoc!(r#"
-                set -eu
+            fs::set_permissions(get_restore_path(&restore_dir, &permissions_dir_path), Permissions::from_mode(0o700))?;
+            fs::remove_dir_all(&restore_dir)?;
 
-                lstree() {{
-                    local time_style_flag="--time-style"
+            // The exported archive must be a complete snapshot
+            let archive_path = temp_dir.join("export.tar");
+            assert!(restoring::export(Path::new(&backup.path), Some(&archive_path), false)?);
 
-                    if [[ "$(uname)" = Darwin && "$(which ls)" = /bin/ls ]]; then
-                        time_style_flag="-D"
-                    fi
+            fs::create_dir(&restore_dir)?;
+            run(["tar", "--xattrs", "-xpf", archive_path.to_str().unwrap(), "-C", restore_dir.to_str().unwrap()])?;
+            check_fifo(&fifo_path, &restore_dir)?;
+            compare_trees(&root_path, &restored_root_path)?;
+            check_xattrs(&xattrs_paths, &restore_dir, &xattrs)?;
 
-                    ls -ARl "$time_style_flag" +%Y.%m.%d-%H:%M:%S
-                }}
+            fs::set_permissions(get_restore_path(&restore_dir, &permissions_dir_path), Permissions::from_mode(0o700))?;
+            fs::remove_dir_all(&restore_dir)?;
+            fs::remove_file(archive_path)?;
 
-                expected="$(cd {root_path:?} && lstree)"
-                actual="$(cd {restored_path:?} && lstree)"
+            // Partial restore of a directory which files have data in other not restored files
+            let selected_path = same_mutable_extern_file_path.parent().unwrap().parent().unwrap();
+            assert!(restoring::restore(
+                Path::new(&backup.path), &restore_dir,
+                RestoreSelection::new(&[selected_path.to_owned()], None)?)?.ok);
 
-                diff -u <(cat <<< "$expected") <(cat <<< "$actual")
-            "#, root_path=root_path, restored_path=restored_root_path))?;
+            compare_trees(selected_path, &get_restore_path(&restore_dir, selected_path))?;
+            assert!(!get_restore_path(&restore_dir, &same_mutable_orig_file_path).exists());
+            assert!(!get_restore_path(&restore_dir, &root_path.join("etc")).exists());
 
-            run(["git", "diff", "--no-index", root_path.to_str().unwrap(), restored_root_path.to_str().unwrap()])?;
+            fs::remove_dir_all(&restore_dir)?;
+
+            // Partial restore of a hard link which target isn't restored
+            let selected_path = &hard_link_paths[1];
+            assert!(restoring::restore(
+                Path::new(&backup.path), &restore_dir,
+                RestoreSelection::new(std::slice::from_ref(selected_path), None)?)?.ok);
+
+            assert_eq!(fs::read(get_restore_path(&restore_dir, selected_path))?, fs::read(selected_path)?);
+            assert!(!get_restore_path(&restore_dir, &hard_link_paths[0]).exists());
 
-            fs::set_permissions(get_restore_path(&restore_dir, &permissions_dir_path), Permissions::from_mode(0o700))?;
             fs::remove_dir_all(restore_dir)?;
             restore_pass += 1;
         }
@@ -318,6 +399,379 @@ fn backup() -> EmptyResult {
     Ok(())
 }
 
+#[test]
+fn chunking() -> EmptyResult {
+    let (temp_dir, source_path, mut config) = prepare_single_item_backup()?;
+    config.backup.as_mut().unwrap().chunking = Some(ChunkingConfig {min_file_size: 64 * 1024, chunk_size: 1024});
+    let restore_dir = temp_dir.join("restore");
+
+    // The number of chunks which are changed by a modification depends on the data, so it's fixed
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut random_data = |size| {
+        let mut data = vec![0; size];
+        rng.fill_bytes(&mut data);
+        data
+    };
+
+    let chunked_path = source_path.join("chunked");
+    fs::write(&chunked_path, random_data(256 * 1024))?;
+
+    let small_path = source_path.join("small");
+    fs::write(&small_path, "small file")?;
+
+    let hard_link_paths = [source_path.join("hard-link-1"), source_path.join("hard-link-2")];
+    fs::write(&hard_link_paths[0], random_data(128 * 1024))?;
+    fs::hard_link(&hard_link_paths[0], &hard_link_paths[1])?;
+
+    let sparse_path = source_path.join("sparse");
+    {
+        let mut file = File::create(&sparse_path)?;
+        file.set_len(1024 * 1024)?;
+        file.seek(SeekFrom::Start(300_000))?;
+        file.write_all(&random_data(10_000))?;
+    }
+
+    let storage = Storage::new_read_only(Filesystem::new(), &config.path);
+
+    assert!(backuping::backup(&config, false)?.ok);
+    let first_backup = get_last_backup(&storage)?;
+    assert!(first_backup.verify(storage.provider.read())?);
+
+    let files = read_metadata(storage.provider.read(), &first_backup)?;
+    assert!(files[&small_path].chunks.is_none());
+    assert!(files[&sparse_path].chunks.is_some());
+    assert!(files[&chunked_path].chunks.as_ref().unwrap().iter().all(|chunk| chunk.unique));
+
+    // The hard link must carry the target's chunks
+    let (target_path, link_path) = if files[&hard_link_paths[0]].hard_link {
+        (&hard_link_paths[1], &hard_link_paths[0])
+    } else {
+        (&hard_link_paths[0], &hard_link_paths[1])
+    };
+    let target_chunks = files[target_path].chunks.as_ref().unwrap();
+    let link_chunks = files[link_path].chunks.as_ref().unwrap();
+    assert!(files[link_path].hard_link);
+    assert!(link_chunks.iter().all(|chunk| !chunk.unique));
+    assert_eq!(
+        link_chunks.iter().map(|chunk| &chunk.hash).collect::<Vec<_>>(),
+        target_chunks.iter().map(|chunk| &chunk.hash).collect::<Vec<_>>());
+
+    let first_source_path = temp_dir.join("first-source");
+    run(["cp", "-a", source_path.to_str().unwrap(), first_source_path.to_str().unwrap()])?;
+
+    // Modify the file in the middle and delete the hard link target, so the link gets its data only
+    // from the previous backup.
+    File::options().write(true).open(&chunked_path)?.write_all_at(b"modified data", 128 * 1024)?;
+    fs::remove_file(target_path)?;
+
+    assert!(backuping::backup(&config, false)?.ok);
+    let second_backup = get_last_backup(&storage)?;
+    assert!(second_backup.verify(storage.provider.read())?);
+
+    let files = read_metadata(storage.provider.read(), &second_backup)?;
+    let chunks = files[&chunked_path].chunks.as_ref().unwrap();
+    let unique_chunks = chunks.iter().filter(|chunk| chunk.unique).count();
+    assert!((1..=3).contains(&unique_chunks), "{} of {} chunks are unique", unique_chunks, chunks.len());
+    assert!(!files[link_path].hard_link);
+    assert!(files[link_path].chunks.as_ref().unwrap().iter().all(|chunk| !chunk.unique));
+
+    for (backup, expected_path) in [(&first_backup, &first_source_path), (&second_backup, &source_path)] {
+        assert!(restoring::restore(Path::new(&backup.path), &restore_dir, RestoreSelection::default())?.ok);
+        compare_trees(expected_path, &get_restore_path(&restore_dir, &source_path))?;
+        assert!(sparse::is_sparse(&fs::metadata(get_restore_path(&restore_dir, &sparse_path))?));
+        fs::remove_dir_all(&restore_dir)?;
+    }
+
+    // Partial restore of a hard link which chunked target isn't restored
+    assert!(restoring::restore(
+        Path::new(&first_backup.path), &restore_dir,
+        RestoreSelection::new(std::slice::from_ref(link_path), None)?)?.ok);
+    assert_eq!(fs::read(get_restore_path(&restore_dir, link_path))?, fs::read(link_path)?);
+    assert!(!get_restore_path(&restore_dir, target_path).exists());
+    fs::remove_dir_all(&restore_dir)?;
+
+    for path in [&chunked_path, link_path] {
+        let mut data = Vec::new();
+        assert!(restoring::cat(Path::new(&second_backup.path), path, &mut data)?);
+        assert_eq!(data, fs::read(path)?);
+    }
+
+    temp_dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn changed_file_deduplication() -> EmptyResult {
+    let (temp_dir, source_path, config) = prepare_single_item_backup()?;
+    let storage = Storage::new_read_only(Filesystem::new(), &config.path);
+    let restore_dir = temp_dir.join("restore");
+
+    // The files are bigger than the buffering limit, so they are written to the archive while being hashed
+    let file_path = source_path.join("file");
+    let copy_path = source_path.join("copy");
+    fs::write(&file_path, "file data\n".repeat(1000))?;
+    fs::write(source_path.join("other"), "other file data\n".repeat(1000))?;
+
+    assert!(backuping::backup(&config, false)?.ok);
+
+    // The file is read as changed one and must be rolled back from the archive as a duplicate
+    filetime::set_file_mtime(&file_path, FileTime::from_unix_time(0, 0))?;
+    fs::copy(&file_path, &copy_path)?;
+
+    let result = backuping::backup(&config, false)?;
+    assert!(result.ok);
+    assert_eq!(result.items[0].stats.new_size, 0);
+
+    let backup = get_last_backup(&storage)?;
+    assert!(backup.verify(storage.provider.read())?);
+
+    let files = read_metadata(storage.provider.read(), &backup)?;
+    assert!(files.values().all(|file| !file.unique));
+    assert_eq!(files[&copy_path].hash, files[&file_path].hash);
+
+    let mut deduplicated_entries = 0;
+    for entry in backup.read_data(storage.provider.read())?.entries()? {
+        let entry = entry?;
+        let path = Path::new("/").join(entry.path()?);
+
+        if path == file_path || path == copy_path {
+            assert_eq!(entry.header().entry_type(), EntryType::Regular);
+            assert_eq!(entry.size(), 0);
+            deduplicated_entries += 1;
+        }
+    }
+    assert_eq!(deduplicated_entries, 2);
+
+    assert!(restoring::restore(Path::new(&backup.path), &restore_dir, RestoreSelection::default())?.ok);
+    compare_trees(&source_path, &get_restore_path(&restore_dir, &source_path))?;
+
+    temp_dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn list() -> EmptyResult {
+    let (temp_dir, source_path, config) = prepare_single_item_backup()?;
+    let storage = Storage::new_read_only(Filesystem::new(), &config.path);
+
+    fs::write(source_path.join("file"), "file data")?;
+    assert!(backuping::backup(&config, false)?.ok);
+    fs::write(source_path.join("other"), "other file data")?;
+    assert!(backuping::backup(&config, false)?.ok);
+
+    let mut output = Vec::new();
+    assert!(inspecting::list(&config, false, OutputFormat::Text, &mut output)?);
+    let output = String::from_utf8(output)?;
+    let lines: Vec<&str> = output.lines().collect();
+
+    let (groups, ok) = storage.get_backup_groups(true)?;
+    assert!(ok);
+    assert_eq!(groups.len(), 1);
+
+    let group = &groups[0];
+    assert_eq!(group.backups.len(), 2);
+    assert_eq!(lines.len(), 5, "{}", output);
+    assert_eq!(lines[0], format!("{}:", storage.name()));
+    assert_eq!(lines[1], format!("  {}:", group.name));
+
+    for (line, backup) in lines[2..4].iter().zip(&group.backups) {
+        assert!(line.starts_with(&format!("    {}: 1 unique files", backup.name)), "{}", line);
+    }
+    assert!(lines[4].starts_with("    Total: 2 unique files"), "{}", lines[4]);
+
+    temp_dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn list_files() -> EmptyResult {
+    let (temp_dir, source_path, config) = prepare_single_item_backup()?;
+    let storage = Storage::new_read_only(Filesystem::new(), &config.path);
+
+    let file_path = source_path.join("file");
+    let dir_path = source_path.join("dir");
+    let nested_paths = [dir_path.join("nested-1"), dir_path.join("nested-2")];
+
+    fs::write(&file_path, "file data")?;
+    fs::create_dir(&dir_path)?;
+    for path in &nested_paths {
+        fs::write(path, "nested file data")?;
+    }
+
+    assert!(backuping::backup(&config, false)?.ok);
+    let backup = get_last_backup(&storage)?;
+    let backup_path = Path::new(&backup.path);
+
+    let list = |path: &Path, recursive: bool, show_data: bool| -> GenericResult<Vec<String>> {
+        let mut output = Vec::new();
+        assert!(inspecting::list_files(backup_path, path, recursive, show_data, &mut output)?);
+        Ok(String::from_utf8(output)?.lines().map(ToOwned::to_owned).collect())
+    };
+
+    let lines = list(&source_path, false, false)?;
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(lines[0].starts_with("dir ") && lines[0].contains(" 2 files "), "{}", lines[0]);
+    assert!(lines[0].ends_with(&format!(" {}/", dir_path.display())), "{}", lines[0]);
+    assert!(lines[1].starts_with("unique ") && lines[1].ends_with(&format!(" {}", file_path.display())),
+            "{}", lines[1]);
+
+    let lines = list(&source_path, true, false)?;
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    for (line, path) in lines.iter().zip(nested_paths.iter().chain([&file_path])) {
+        assert!(line.ends_with(&format!(" {}", path.display())), "{}", line);
+    }
+
+    // Data archive listing includes the directories themselves
+    let lines = list(&dir_path, false, true)?;
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    assert!(lines[0].starts_with('d') && lines[0].ends_with(&format!(" {}", dir_path.display())), "{}", lines[0]);
+    for (line, path) in lines[1..].iter().zip(&nested_paths) {
+        assert!(line.starts_with('-') && line.ends_with(&format!(" {}", path.display())), "{}", line);
+    }
+
+    assert!(list(&source_path.join("missing"), false, false).is_err());
+
+    temp_dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn diff() -> EmptyResult {
+    let (temp_dir, source_path, config) = prepare_single_item_backup()?;
+    let storage = Storage::new_read_only(Filesystem::new(), &config.path);
+
+    let same_path = source_path.join("same");
+    let removed_path = source_path.join("removed");
+    let modified_path = source_path.join("modified");
+    let added_path = source_path.join("added");
+
+    fs::write(&same_path, "same data")?;
+    fs::write(&removed_path, "removed data")?;
+    fs::write(&modified_path, "original data")?;
+    assert!(backuping::backup(&config, false)?.ok);
+    let old_backup = get_last_backup(&storage)?;
+
+    fs::remove_file(&removed_path)?;
+    fs::write(&modified_path, "modified file data")?;
+    fs::write(&added_path, "added data")?;
+    assert!(backuping::backup(&config, false)?.ok);
+    let new_backup = get_last_backup(&storage)?;
+
+    let mut output = Vec::new();
+    assert!(inspecting::diff(Path::new(&old_backup.path), Path::new(&new_backup.path), &mut output)?);
+    let output = String::from_utf8(output)?;
+
+    let mut changes: HashMap<&str, Vec<PathBuf>> = HashMap::new();
+    for line in output.lines() {
+        if let Some((kind, rest)) = line.split_once(' ') && ["+", "-", "M"].contains(&kind) {
+            let path = rest.split(' ').find(|part| part.starts_with('/')).unwrap();
+            changes.entry(kind).or_default().push(PathBuf::from(path));
+        }
+    }
+
+    assert_eq!(changes, HashMap::from([
+        ("+", vec![added_path]),
+        ("-", vec![removed_path]),
+        ("M", vec![modified_path]),
+    ]));
+    assert!(output.contains("\nAdded: 1 files"), "{}", output);
+    assert!(output.contains("\nRemoved: 1 files"), "{}", output);
+    assert!(output.contains("\nModified: 1 files"), "{}", output);
+
+    temp_dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn versions() -> EmptyResult {
+    let (temp_dir, source_path, config) = prepare_single_item_backup()?;
+    let storage = Storage::new_read_only(Filesystem::new(), &config.path);
+
+    let file_path = source_path.join("file");
+    fs::write(&file_path, "original data")?;
+    fs::write(source_path.join("other"), "other data")?;
+
+    for contents in [None, Some("modified file data")] {
+        if let Some(contents) = contents {
+            fs::write(&file_path, contents)?;
+        }
+        assert!(backuping::backup(&config, false)?.ok);
+        assert!(backuping::backup(&config, false)?.ok);
+    }
+
+    let (groups, ok) = storage.get_backup_groups(true)?;
+    assert!(ok);
+    let backups: Vec<&str> = groups.iter()
+        .flat_map(|group| &group.backups).map(|backup| backup.name.as_str()).collect();
+    assert_eq!(backups.len(), 4);
+
+    let mut output = Vec::new();
+    assert!(inspecting::versions(&config, file_path.to_str().unwrap(), &mut output)?);
+    let output = String::from_utf8(output)?;
+    let lines: Vec<&str> = output.lines().collect();
+
+    assert_eq!(lines.len(), 3, "{}", output);
+    assert_eq!(lines[0], format!("{}:", file_path.display()));
+    assert!(lines[1].starts_with(&format!("  {} - {} (2 backups): ", backups[0], backups[1])), "{}", lines[1]);
+    assert!(lines[2].starts_with(&format!("  {} - {} (2 backups): ", backups[2], backups[3])), "{}", lines[2]);
+
+    let missing_path = source_path.join("missing");
+    assert!(inspecting::versions(&config, missing_path.to_str().unwrap(), &mut Vec::new()).is_err());
+
+    temp_dir.close()?;
+    Ok(())
+}
+
+fn compare_trees(expected_path: &Path, actual_path: &Path) -> EmptyResult {
+    shell(&formatdoc!(r#"
+        set -eu
+
+        lstree() {{
+            local time_style_flag="--time-style"
+
+            if [[ "$(uname)" = Darwin && "$(which ls)" = /bin/ls ]]; then
+                time_style_flag="-D"
+            fi
+
+            # Ignore disk usage which depends on sparse files extraction
+            ls -ARl "$time_style_flag" +%Y.%m.%d-%H:%M:%S | grep -v '^total '
+        }}
+
+        expected="$(cd {expected_path:?} && lstree)"
+        actual="$(cd {actual_path:?} && lstree)"
+
+        diff -u <(cat <<< "$expected") <(cat <<< "$actual")
+    "#, expected_path=expected_path, actual_path=actual_path))?;
+
+    run(["git", "diff", "--no-index", expected_path.to_str().unwrap(), actual_path.to_str().unwrap()])
+}
+
+fn check_xattrs(paths: &[PathBuf], restore_dir: &Path, expected: &Xattrs) -> EmptyResult {
+    for path in paths {
+        let restored_path = get_restore_path(restore_dir, path);
+        let xattrs: Xattrs = xattr::read(&restored_path)?.into_iter()
+            .filter(|(name, _)| name.starts_with("user.")).collect();
+        assert_eq!(&xattrs, expected, "Invalid {:?} extended attributes", restored_path);
+    }
+    Ok(())
+}
+
+// Checks the restored FIFO and deletes it to make the tree comparable with the source one
+fn check_fifo(path: &Path, restore_dir: &Path) -> EmptyResult {
+    let restored_path = get_restore_path(restore_dir, path);
+    let metadata = fs::symlink_metadata(&restored_path)?;
+    assert!(metadata.file_type().is_fifo(), "{:?} is not a FIFO", restored_path);
+    assert_eq!(metadata.mode() & 0o7777, 0o640);
+
+    let parent_path = path.parent().unwrap();
+    fs::remove_file(&restored_path)?;
+    filetime::set_file_mtime(
+        get_restore_path(restore_dir, parent_path),
+        FileTime::from_system_time(fs::metadata(parent_path)?.modified()?))?;
+
+    Ok(())
+}
+
 struct GitRestorer(Vec<PathBuf>);
 
 impl GitRestorer {
@@ -415,6 +869,41 @@ fn shell(command: &str) -> EmptyResult {
     run(["bash", "-c", command])
 }
 
+// Prepares a temporary directory with the source directory and backup storage for the tests which back up a
+// single directory
+fn prepare_single_item_backup() -> GenericResult<(TempDir, PathBuf, BackupSpecConfig)> {
+    let temp_dir = TempDir::new()?;
+    let source_path = temp_dir.path().canonicalize()?.join("source");
+    let backup_root_path = temp_dir.join("backups");
+    fs::create_dir(&source_path)?;
+    fs::create_dir(&backup_root_path)?;
+
+    let config = BackupSpecConfig {
+        name: "test".to_owned(),
+        path: backup_root_path.to_str().unwrap().to_owned(),
+        backup: Some(BackupConfig {
+            items: vec![BackupItemConfig {
+                path: source_path.to_str().unwrap().to_owned(),
+                filter: PathFilter::default(), before: None, after: None, compress: true,
+                one_file_system: false, filesystems: Vec::new(),
+            }],
+            max_backup_groups: 1,
+            max_backups_per_group: 10,
+            compression: CompressionConfig::default(),
+            chunking: None,
+        }),
+        upload: None
+    };
+
+    Ok((temp_dir, source_path, config))
+}
+
+fn get_last_backup(storage: &Storage) -> GenericResult<Backup> {
+    let (groups, ok) = storage.get_backup_groups(true)?;
+    assert!(ok);
+    Ok(groups.into_iter().next_back().unwrap().backups.pop().unwrap())
+}
+
 fn get_restore_path(restore_dir: &Path, path: &Path) -> PathBuf {
     let mut components = path.components();
     assert_eq!(components.next(), Some(Component::RootDir));
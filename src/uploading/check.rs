@@ -1,60 +1,70 @@
 use std::time::Duration;
 
-use log::{warn, error};
+use log::{Level, log};
 
 use crate::storage::{Storage, BackupGroup};
 
+pub type Finding = (Level, String);
+
+pub fn log_findings(findings: Vec<Finding>) {
+    for (level, message) in findings {
+        log!(level, "{}.", message);
+    }
+}
+
 pub fn check_backups(storage: &Storage, backup_groups: &[BackupGroup], consistent: bool,
-                     max_time_without_backups: Option<Duration>) {
+                     max_time_without_backups: Option<Duration>) -> Vec<Finding> {
+    let mut findings = Vec::new();
     let mut last_backup = None;
 
     for group in backup_groups {
         if let Some(backup) = group.backups.iter().next_back() {
             last_backup = Some(backup);
         } else {
-            let error = format!("{} has an empty {:?} backup group.", storage.name(), group.name);
-            if consistent {
-                error!("{}", error);
+            let level = if consistent {
+                Level::Error
             } else {
-                warn!("{}", error);
-            }
+                Level::Warn
+            };
+            findings.push((level, format!("{} has an empty {:?} backup group", storage.name(), group.name)));
         }
     }
 
     let last_backup = match last_backup {
         Some(last_backup) => last_backup,
         None => {
-            error!("{} have no backups.", storage.name());
-            return;
+            findings.push((Level::Error, format!("{} have no backups", storage.name())));
+            return findings;
         }
     };
 
     let max_time_without_backups = match max_time_without_backups {
         Some(duration) => duration,
-        None => return,
+        None => return findings,
     };
 
     let last_backup_time = match storage.get_backup_time(&last_backup.name) {
         Ok(last_backup_time) => last_backup_time,
         Err(err) => {
-            error!("Failed to determine a time when backup has been created: {}.", err);
-            return;
+            findings.push((Level::Error, format!(
+                "Failed to determine a time when backup has been created: {}", err)));
+            return findings;
         }
     };
 
     let time_from_last_backup = match last_backup_time.elapsed() {
         Ok(duration) => duration,
         Err(_) => {
-            error!(concat!(
+            findings.push((Level::Error, format!(concat!(
                 "Failed to check last backup time: ",
-                "the latest backup ({:?}) on {} has backup time in the future."),
-                last_backup.name, storage.name());
-            return;
+                "the latest backup ({:?}) on {} has backup time in the future"),
+                last_backup.name, storage.name())));
+            return findings;
         }
     };
 
     if time_from_last_backup < max_time_without_backups {
-        return;
+        return findings;
     }
 
     let minute_seconds = 60;
@@ -76,5 +86,8 @@ pub fn check_backups(storage: &Storage, backup_groups: &[BackupGroup], consisten
         }
     }
 
-    error!("{} doesn't have any backup for last {}.", storage.name(), human_durations.join(" "));
+    findings.push((Level::Error, format!(
+        "{} doesn't have any backup for last {}", storage.name(), human_durations.join(" "))));
+
+    findings
 }
\ No newline at end of file
@@ -32,18 +32,10 @@ pub enum ProviderConfig {
         /*
         How to obtain the credentials:
 
-        open https://www.dropbox.com/developers/apps
-        client_id=...     # App key
-        client_secret=... # App secret
+        Create the application - https://www.dropbox.com/developers/apps
+        Add http://127.0.0.1:$port/ to its redirect URIs
 
-        open "https://www.dropbox.com/oauth2/authorize?client_id=$client_id&response_type=code&token_access_type=offline"
-        code=...
-
-        curl "https://api.dropbox.com/oauth2/token" -d grant_type=authorization_code -d "code=$code" -d "client_id=$client_id" -d "client_secret=$client_secret"
-        refresh_token=...
-
-        # Test access token acquiring
-        curl "https://api.dropbox.com/oauth2/token" -d grant_type=refresh_token -d "refresh_token=$refresh_token" -d "client_id=$client_id" -d "client_secret=$client_secret"
+        vsb auth dropbox --port "$port" --client-id "$app_key" --client-secret "$app_secret"
         */
         client_id: String,
         client_secret: String,
@@ -56,14 +48,9 @@ pub enum ProviderConfig {
         How to obtain the credentials (see https://developers.google.com/identity/protocols/oauth2/native-app):
 
         Enable Google Drive API - https://console.developers.google.com/
-        Create OAuth client - https://console.developers.google.com/apis/credentials
+        Create OAuth client of "Desktop app" type - https://console.developers.google.com/apis/credentials
 
-        client_id=...
-        client_secret=...
-        open "https://accounts.google.com/o/oauth2/v2/auth?scope=https://www.googleapis.com/auth/drive&access_type=offline&response_type=code&redirect_uri=urn:ietf:wg:oauth:2.0:oob&client_id=$client_id"
-
-        code=...
-        curl https://oauth2.googleapis.com/token -d grant_type=authorization_code -d "client_id=$client_id" -d "client_secret=$client_secret" -d "code=$code" -d "redirect_uri=urn:ietf:wg:oauth:2.0:oob"
+        vsb auth google-drive --client-id "$client_id" --client-secret "$client_secret"
 
         But, since June 2022 it's not usable because obtained refresh token has expire time of 7 days
         if your application is in Testing mode, but to publish the application you must go through
@@ -80,15 +67,9 @@ pub enum ProviderConfig {
         How to obtain the credentials:
 
         Register the application - https://oauth.yandex.ru/
-        Callback URL: https://oauth.yandex.ru/verification_code
-
-        client_id=...
-        client_secret=...
-        device_name=Server
-        open "https://oauth.yandex.ru/authorize?response_type=code&client_id=$client_id&device_name=$device_name"
+        Redirect URI: http://127.0.0.1:$port/
 
-        code=...
-        curl https://oauth.yandex.ru/token -d grant_type=authorization_code -d "client_id=$client_id" -d "client_secret=$client_secret" -d "code=$code"
+        vsb auth yandex-disk --port "$port" --client-id "$client_id" --client-secret "$client_secret"
         */
         client_id: String,
         client_secret: String,
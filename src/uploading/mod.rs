@@ -12,9 +12,10 @@ use crate::providers::dropbox::Dropbox;
 use crate::providers::filesystem::Filesystem;
 use crate::providers::google_drive::GoogleDrive;
 use crate::providers::yandex_disk::YandexDisk;
-use crate::storage::{BackupGroup, Storage};
+use crate::storage::{BackupGroup, Storage, StorageRc};
 use crate::util::sys::acquire_lock;
 
+pub use check::{Finding, check_backups};
 pub use config::{UploadConfig, ProviderConfig};
 
 pub fn upload(config: &Config, verify: bool) -> GenericResult<bool> {
@@ -59,8 +60,8 @@ fn sync_backups(
     let local_storage = Storage::new_read_only(Filesystem::new(), path);
 
     let (local_backup_groups, local_ok) = get_backup_groups(&local_storage, verify)?;
-    check::check_backups(&local_storage, &local_backup_groups,
-                         local_ok, config.max_time_without_backups);
+    check::log_findings(check::check_backups(
+        &local_storage, &local_backup_groups, local_ok, config.max_time_without_backups));
 
     if collect_metrics {
         if let Err(err) = metrics::collect(name, &local_backup_groups) {
@@ -68,14 +69,7 @@ fn sync_backups(
         }
     }
 
-    let cloud_storage = match config.provider {
-        ProviderConfig::Dropbox {ref client_id, ref client_secret, ref refresh_token} =>
-            Storage::new_upload(Dropbox::new(client_id, client_secret, refresh_token)?, &config.path),
-        ProviderConfig::GoogleDrive {ref client_id, ref client_secret, ref refresh_token} =>
-            Storage::new_upload(GoogleDrive::new(client_id, client_secret, refresh_token), &config.path),
-        ProviderConfig::YandexDisk {ref client_id, ref client_secret, ref refresh_token} =>
-            Storage::new_upload(YandexDisk::new(client_id, client_secret, refresh_token)?, &config.path),
-    };
+    let cloud_storage = get_cloud_storage(config)?;
     let (cloud_backup_groups, cloud_ok) = get_backup_groups(&cloud_storage, false)?;
 
     info!("Syncing...");
@@ -91,12 +85,23 @@ fn sync_backups(
             return Ok(());
         },
     };
-    check::check_backups(&cloud_storage, &cloud_backup_groups,
-                         sync_ok && cloud_ok, config.max_time_without_backups);
+    check::log_findings(check::check_backups(
+        &cloud_storage, &cloud_backup_groups, sync_ok && cloud_ok, config.max_time_without_backups));
 
     Ok(())
 }
 
+pub fn get_cloud_storage(config: &UploadConfig) -> GenericResult<StorageRc> {
+    Ok(match config.provider {
+        ProviderConfig::Dropbox {ref client_id, ref client_secret, ref refresh_token} =>
+            Storage::new_upload(Dropbox::new(client_id, client_secret, refresh_token)?, &config.path),
+        ProviderConfig::GoogleDrive {ref client_id, ref client_secret, ref refresh_token} =>
+            Storage::new_upload(GoogleDrive::new(client_id, client_secret, refresh_token), &config.path),
+        ProviderConfig::YandexDisk {ref client_id, ref client_secret, ref refresh_token} =>
+            Storage::new_upload(YandexDisk::new(client_id, client_secret, refresh_token)?, &config.path),
+    })
+}
+
 fn get_backup_groups(storage: &Storage, verify: bool) -> GenericResult<(Vec<BackupGroup>, bool)> {
     info!("Checking backups on {}...", storage.name());
     let (groups, ok) = storage.get_backup_groups(verify).map_err(|e| format!(
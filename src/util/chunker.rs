@@ -0,0 +1,183 @@
+// Content-defined chunking.
+//
+// Chunk boundaries are found by gear rolling hash (FastCDC algorithm with normalized chunking), so
+// they depend only on the data around them, and an insertion or deletion in the middle of the file
+// changes only the chunks around the change. The gear table and boundary conditions define the
+// chunks which are deduplicated across backups, so they must never be changed.
+
+use std::cmp;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use digest::Digest;
+
+use crate::core::GenericResult;
+use crate::util::hash::Hash;
+
+const CHUNKS_DIR: &str = "vsb-chunks";
+
+pub struct Chunker<R: Read> {
+    reader: R,
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    // Boundary masks for chunks which are smaller and bigger than the average size
+    small_mask: u64,
+    large_mask: u64,
+    buffer: Vec<u8>,
+    position: usize,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    // The average chunk size must be a power of two. Chunks are from 1/4 to 4 times of it (see the
+    // metadata format description).
+    pub fn new(reader: R, avg_size: usize) -> Chunker<R> {
+        assert!(avg_size.is_power_of_two() && avg_size >= 64);
+        let bits = avg_size.trailing_zeros();
+
+        let max_size = avg_size * 4;
+
+        Chunker {
+            reader,
+            min_size: avg_size / 4,
+            avg_size,
+            max_size,
+            small_mask: !0 << (64 - (bits + 2)),
+            large_mask: !0 << (64 - (bits - 2)),
+            buffer: Vec::with_capacity(max_size),
+            position: 0,
+            eof: false,
+        }
+    }
+
+    // Returns the next chunk or None if all data has been read
+    pub fn next_chunk(&mut self) -> io::Result<Option<&[u8]>> {
+        self.buffer.drain(..self.position);
+        self.position = 0;
+
+        while !self.eof && self.buffer.len() < self.max_size {
+            let size = self.buffer.len();
+            self.buffer.resize(self.max_size, 0);
+
+            match self.reader.read(&mut self.buffer[size..]) {
+                Ok(read_size) => {
+                    self.buffer.truncate(size + read_size);
+                    self.eof = read_size == 0;
+                },
+                Err(err) => {
+                    self.buffer.truncate(size);
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                },
+            }
+        }
+
+        if self.buffer.is_empty() {
+            return Ok(None);
+        }
+
+        self.position = self.find_boundary(&self.buffer);
+        Ok(Some(&self.buffer[..self.position]))
+    }
+
+    fn find_boundary(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+
+        let mut hash: u64 = 0;
+        let normal_size = cmp::min(self.avg_size, data.len());
+
+        for (position, &byte) in data.iter().enumerate().skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+
+            let mask = if position < normal_size {
+                self.small_mask
+            } else {
+                self.large_mask
+            };
+
+            if hash & mask == 0 {
+                return position + 1;
+            }
+        }
+
+        data.len()
+    }
+}
+
+pub fn get_chunk_hash(data: &[u8]) -> Hash {
+    sha2::Sha512::digest(data).as_slice().into()
+}
+
+// Chunks are stored in data archive as contiguous file entries (which are extracted as regular files by
+// tar implementations), so they can't be confused with the backed up files.
+pub fn get_chunk_path(hash: &Hash) -> PathBuf {
+    Path::new(CHUNKS_DIR).join(hash.to_string())
+}
+
+pub fn parse_chunk_path(path: &Path) -> GenericResult<Hash> {
+    path.strip_prefix(CHUNKS_DIR).ok().and_then(|name| name.to_str()).ok_or_else(|| format!(
+        "Invalid chunk path: {:?}", path))?.try_into()
+}
+
+// Pseudo-random values generated by SplitMix64 with zero seed
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut index = 0;
+
+    while index < table.len() {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[index] = value ^ (value >> 31);
+        index += 1;
+    }
+
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    #[test]
+    fn chunking() {
+        let avg_size = 1024;
+
+        // The number of chunks which are changed by an insertion depends on the data, so it's fixed
+        let mut data = vec![0; 100 * avg_size];
+        StdRng::seed_from_u64(0).fill_bytes(&mut data);
+
+        let split = |data: &[u8]| -> Vec<Vec<u8>> {
+            let mut chunks = Vec::new();
+            let mut chunker = Chunker::new(data, avg_size);
+
+            while let Some(chunk) = chunker.next_chunk().unwrap() {
+                assert!(chunk.len() <= avg_size * 4);
+                chunks.push(chunk.to_vec());
+            }
+
+            assert_eq!(chunks.concat(), data);
+            chunks
+        };
+
+        let chunks = split(&data);
+        assert!(chunks.len() > 50 && chunks.len() < 200, "{}", chunks.len());
+        assert!(chunks[..chunks.len() - 1].iter().all(|chunk| chunk.len() > avg_size / 4));
+
+        // An insertion must change only the chunks around it
+        let mut changed_data = data.clone();
+        changed_data.splice(data.len() / 2..data.len() / 2, b"inserted data".iter().cloned());
+
+        let changed_chunks = split(&changed_data);
+        let same_chunks = changed_chunks.iter().filter(|chunk| chunks.contains(chunk)).count();
+        assert!(same_chunks >= chunks.len() - 3, "{} of {}", same_chunks, chunks.len());
+    }
+}
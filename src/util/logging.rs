@@ -0,0 +1,72 @@
+// Logging with per-thread context.
+//
+// easy_logging's global context can't be used for tasks which are run in parallel, so such tasks set
+// a thread context which prefixes messages of the thread and is inherited by its helper threads and jobs.
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use log::{Log, Metadata, Record, SetLoggerError};
+
+thread_local! {
+    static CONTEXT: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+pub fn init(main_module_name: &'static str, level: log::Level) -> Result<(), SetLoggerError> {
+    let (max_level, logger) = easy_logging::LoggingConfig::new(main_module_name, level).dispatch().into_log();
+    log::set_boxed_logger(Box::new(ContextLogger(logger)))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+pub struct ThreadContext {
+    previous: Option<Arc<str>>,
+}
+
+impl ThreadContext {
+    pub fn new(name: &str) -> ThreadContext {
+        ThreadContext::inherit(Some(name.into()))
+    }
+
+    // Sets the context acquired from another thread
+    pub fn inherit(context: Option<Arc<str>>) -> ThreadContext {
+        ThreadContext {
+            previous: CONTEXT.with(|current| current.replace(context)),
+        }
+    }
+
+    pub fn current() -> Option<Arc<str>> {
+        CONTEXT.with(|current| current.borrow().clone())
+    }
+}
+
+impl Drop for ThreadContext {
+    fn drop(&mut self) {
+        CONTEXT.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+struct ContextLogger(Box<dyn Log>);
+
+impl Log for ContextLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        match ThreadContext::current() {
+            Some(context) => self.0.log(&Record::builder()
+                .args(format_args!("[{}] {}", context, record.args()))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build()),
+            None => self.0.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
@@ -1,4 +1,9 @@
+pub mod chunker;
 pub mod file_reader;
 pub mod hash;
+pub mod logging;
+pub mod output;
+pub mod sparse;
 pub mod stream_splitter;
-pub mod sys;
\ No newline at end of file
+pub mod sys;
+pub mod xattr;
\ No newline at end of file
@@ -0,0 +1,44 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::core::EmptyResult;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn is_json(self) -> bool {
+        self == OutputFormat::Json
+    }
+}
+
+// The original stdout if it's been redirected to stderr
+static STDOUT: Mutex<Option<File>> = Mutex::new(None);
+
+// Logging writes info messages to stdout, so the process stdout is redirected to stderr to keep the
+// original stdout clean for the command output. Must be called before logging initialization.
+pub fn redirect_stdout() -> EmptyResult {
+    let stdout = nix::unistd::dup(io::stdout())?;
+    nix::unistd::dup2_stdout(io::stderr())?;
+    STDOUT.lock().unwrap().replace(stdout.into());
+    Ok(())
+}
+
+pub fn print_json<T: Serialize>(value: &T) -> EmptyResult {
+    let mut original_stdout = STDOUT.lock().unwrap();
+
+    let mut stdout: Box<dyn Write> = match original_stdout.as_mut() {
+        Some(file) => Box::new(BufWriter::new(file)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    serde_json::to_writer_pretty(&mut stdout, value)?;
+    writeln!(stdout)?;
+    Ok(stdout.flush()?)
+}
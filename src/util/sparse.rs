@@ -0,0 +1,268 @@
+// Sparse files support.
+//
+// Holes are detected using SEEK_DATA/SEEK_HOLE and the file is stored in the archive as old GNU sparse
+// entry which is supported by tar crate and all major tar implementations. Data regions are aligned
+// to the archive block size as the format requires, so the stored data may contain some zeros.
+
+use std::cmp;
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use nix::errno::Errno;
+use nix::unistd::{self, Whence};
+use tar::{Builder, EntryType, GnuExtSparseHeader, GnuSparseHeader, Header};
+
+const BLOCK_SIZE: u64 = 512;
+
+pub type Regions = Vec<Range<u64>>;
+
+// A cheap check whether the file may have holes
+pub fn is_sparse(metadata: &Metadata) -> bool {
+    metadata.blocks() * 512 < metadata.len()
+}
+
+// Returns data regions of the file or None if it has no holes
+pub fn get_data_regions(file: &File, size: u64) -> io::Result<Option<Regions>> {
+    let mut regions = Regions::new();
+    let mut offset = 0;
+
+    while offset < size {
+        let start = match unistd::lseek(file, offset as libc::off_t, Whence::SeekData) {
+            Ok(start) => start as u64,
+            // There is no data after the offset
+            Err(Errno::ENXIO) => break,
+            // The filesystem doesn't support holes detection
+            Err(Errno::EINVAL) if offset == 0 => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if start >= size {
+            break;
+        }
+
+        let end = unistd::lseek(file, start as libc::off_t, Whence::SeekHole)? as u64;
+        let start = start / BLOCK_SIZE * BLOCK_SIZE;
+        let end = cmp::min(end.div_ceil(BLOCK_SIZE) * BLOCK_SIZE, size);
+
+        match regions.last_mut() {
+            Some(last) if last.end >= start => last.end = end,
+            _ => regions.push(start..end),
+        }
+
+        offset = end;
+    }
+
+    if regions.len() == 1 && regions[0] == (0..size) {
+        return Ok(None);
+    }
+
+    Ok(Some(regions))
+}
+
+// Appends the file as GNU sparse entry. The data must contain only the data regions.
+pub fn append<W: Write, R: Read>(
+    archive: &mut Builder<W>, header: &mut Header, path: &Path, size: u64, regions: &Regions, data: R,
+) -> io::Result<()> {
+    let mut blocks: Vec<(u64, u64)> = regions.iter().map(|region| (region.start, region.end - region.start)).collect();
+
+    // The file must end with a block which may be an empty one if the file ends with a hole
+    if regions.last().is_none_or(|region| region.end < size) {
+        blocks.push((size, 0));
+    }
+
+    let (blocks, extended_blocks) = blocks.split_at(cmp::min(blocks.len(), 4));
+
+    header.set_entry_type(EntryType::GNUSparse);
+    header.set_size(regions.iter().map(|region| region.end - region.start).sum());
+
+    let gnu = header.as_gnu_mut().ok_or_else(|| io::Error::other("Got a non-GNU tar header"))?;
+    gnu.set_real_size(size);
+    gnu.set_is_extended(!extended_blocks.is_empty());
+    set_blocks(&mut gnu.sparse, blocks);
+
+    // Extended sparse headers follow the entry header
+    let mut extended_headers = Vec::new();
+    let mut chunks = extended_blocks.chunks(21).peekable();
+
+    while let Some(blocks) = chunks.next() {
+        let mut extended_header = GnuExtSparseHeader::new();
+        extended_header.set_is_extended(chunks.peek().is_some());
+        set_blocks(extended_header.sparse_mut(), blocks);
+        extended_headers.extend(extended_header.as_bytes());
+    }
+
+    archive.append_data(header, path, extended_headers.as_slice().chain(data))
+}
+
+// Converts GNU sparse entry header to a regular file header
+pub fn to_regular(header: &mut Header, size: u64) {
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(size);
+
+    if let Some(gnu) = header.as_gnu_mut() {
+        gnu.realsize = Default::default();
+        gnu.isextended = Default::default();
+        for block in &mut gnu.sparse {
+            block.offset = Default::default();
+            block.numbytes = Default::default();
+        }
+    }
+}
+
+fn set_blocks(headers: &mut [GnuSparseHeader], blocks: &[(u64, u64)]) {
+    for (header, &(offset, size)) in headers.iter_mut().zip(blocks) {
+        header.set_offset(offset);
+        header.set_length(size);
+    }
+}
+
+// Reads the file skipping the holes and returning zeros for them instead
+pub struct SparseFileReader<'a> {
+    file: &'a mut File,
+    regions: &'a [Range<u64>],
+    size: u64,
+    position: u64,
+    // Unknown initially: the file offset is changed by holes detection
+    file_position: Option<u64>,
+}
+
+impl<'a> SparseFileReader<'a> {
+    pub fn new(file: &'a mut File, regions: &'a [Range<u64>], size: u64) -> SparseFileReader<'a> {
+        SparseFileReader {file, regions, size, position: 0, file_position: None}
+    }
+}
+
+impl Read for SparseFileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(region) = self.regions.first() && self.position >= region.end {
+            self.regions = &self.regions[1..];
+        }
+
+        let hole_end = match self.regions.first() {
+            Some(region) if self.position >= region.start => {
+                if self.file_position != Some(self.position) {
+                    self.file.seek(SeekFrom::Start(self.position))?;
+                }
+
+                let size = cmp::min(buf.len() as u64, region.end - self.position) as usize;
+                let size = self.file.read(&mut buf[..size])?;
+                self.position += size as u64;
+                self.file_position = Some(self.position);
+
+                return Ok(size);
+            },
+            Some(region) => region.start,
+            None => self.size,
+        };
+
+        let size = cmp::min(buf.len() as u64, hole_end.saturating_sub(self.position)) as usize;
+        buf[..size].fill(0);
+        self.position += size as u64;
+
+        Ok(size)
+    }
+}
+
+// Passes through only the data regions of the file contents
+pub struct SparseDataReader<'a, R: Read> {
+    reader: R,
+    regions: &'a [Range<u64>],
+    position: u64,
+}
+
+impl<'a, R: Read> SparseDataReader<'a, R> {
+    pub fn new(reader: R, regions: &'a [Range<u64>]) -> SparseDataReader<'a, R> {
+        SparseDataReader {reader, regions, position: 0}
+    }
+}
+
+impl<R: Read> Read for SparseDataReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(region) = self.regions.first() && self.position >= region.end {
+            self.regions = &self.regions[1..];
+        }
+
+        let Some(region) = self.regions.first() else {
+            // Consume the rest of the contents (it might be hashed by the underlying reader)
+            io::copy(&mut self.reader, &mut io::sink())?;
+            return Ok(0);
+        };
+
+        if self.position < region.start {
+            let size = region.start - self.position;
+            if io::copy(&mut (&mut self.reader).take(size), &mut io::sink())? != size {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.position = region.start;
+        }
+
+        let size = cmp::min(buf.len() as u64, region.end - self.position) as usize;
+        let size = self.reader.read(&mut buf[..size])?;
+        self.position += size as u64;
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::os::unix::fs::FileExt;
+
+    use digest::Digest;
+    use tar::Archive;
+
+    use crate::util::file_reader::FileReader;
+    use crate::util::hash::Hash;
+    use crate::util::sys;
+
+    use super::*;
+
+    #[test]
+    fn sparse() {
+        let size = 100 * 4096 + 10;
+        let mut contents = vec![0; size as usize];
+
+        let mut file = sys::create_temp_file().unwrap();
+        file.set_len(size).unwrap();
+
+        // Unaligned data blocks starting from the beginning of the file and ending with a hole
+        for index in 0..30 {
+            let offset = index * 3 * 4096 + index * 10;
+            let data = [index as u8 + 1; 100];
+            file.write_all_at(&data, offset).unwrap();
+            contents[offset as usize..offset as usize + data.len()].copy_from_slice(&data);
+        }
+
+        let regions = get_data_regions(&file, size).unwrap().unwrap();
+        assert_eq!(regions.len(), 30);
+
+        let mut archive = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+
+        let mut file = SparseFileReader::new(&mut file, &regions, size);
+        let mut file_reader = FileReader::new(&mut file, size);
+        let data = SparseDataReader::new(&mut file_reader, &regions);
+        append(&mut archive, &mut header, Path::new("sparse"), size, &regions, data).unwrap();
+
+        let (bytes_read, hash) = file_reader.consume();
+        assert_eq!(bytes_read, size);
+        assert_eq!(hash, Hash::from(sha2::Sha512::digest(&contents).as_slice()));
+
+        let mut archive = Archive::new(Cursor::new(archive.into_inner().unwrap()));
+        let mut entries = archive.entries().unwrap();
+
+        let mut entry = entries.next().unwrap().unwrap();
+        assert_eq!(entry.header().entry_type(), EntryType::GNUSparse);
+        assert_eq!(entry.size(), size);
+
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        assert!(data == contents);
+
+        assert!(entries.next().is_none());
+    }
+}
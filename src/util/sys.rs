@@ -2,7 +2,7 @@ use std::io;
 use std::fs::{File, OpenOptions};
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::IntoRawFd;
-use std::path::{Path, Component};
+use std::path::{Path, PathBuf, Component};
 use std::thread;
 use std::time::{self, Duration};
 
@@ -19,6 +19,25 @@ pub fn is_root_path(path: &Path) -> bool {
     components.next() == Some(Component::RootDir) && components.next().is_none()
 }
 
+pub fn normalize_path(path: &Path) -> GenericResult<PathBuf> {
+    let mut components = path.components();
+    let mut normalized = PathBuf::from("/");
+
+    if components.next() != Some(Component::RootDir) {
+        return Err!("Invalid path: {:?}. It must be absolute", path);
+    }
+
+    for component in components {
+        match component {
+            Component::Normal(_) => normalized.push(component),
+            Component::CurDir => {},
+            _ => return Err!("Invalid path: {:?}", path),
+        }
+    }
+
+    Ok(normalized)
+}
+
 pub fn acquire_lock<P: AsRef<Path>>(path: P) -> GenericResult<Flock<File>> {
     let path = path.as_ref();
     let file = File::open(path).map_err(|e| format!(
@@ -33,12 +52,89 @@ pub fn acquire_lock<P: AsRef<Path>>(path: P) -> GenericResult<Flock<File>> {
     })?)
 }
 
+// Creates an anonymous temporary file which is deleted on close
+pub fn create_temp_file() -> io::Result<File> {
+    let (fd, path) = unistd::mkstemp(&std::env::temp_dir().join("vsb.XXXXXX"))?;
+    unistd::unlink(&path)?;
+    Ok(fd.into())
+}
+
 pub fn fsync_directory(path: &Path) -> io::Result<()> {
     let mut open_options = OpenOptions::new();
     open_options.read(true).custom_flags(OFlag::O_NOFOLLOW.bits());
     open_options.open(path)?.sync_all()
 }
 
+// Returns free space available to unprivileged users on the filesystem which the path belongs to
+#[allow(clippy::useless_conversion)] // statvfs field types are platform-dependent
+pub fn get_free_space(path: &Path) -> GenericResult<u64> {
+    let stat = sys::statvfs::statvfs(path).map_err(|e| format!(
+        "Unable to get {:?} filesystem info: {}", path, e))?;
+    Ok(u64::from(stat.blocks_available()) * u64::from(stat.fragment_size()))
+}
+
+// Returns type of the filesystem which the path belongs to
+#[cfg(not(target_os = "macos"))]
+pub fn get_filesystem_type(path: &Path) -> GenericResult<String> {
+    let mounts = std::fs::read("/proc/self/mountinfo").map_err(|e| format!(
+        "Unable to read /proc/self/mountinfo: {}", e))?;
+    get_mount_filesystem_type(&String::from_utf8_lossy(&mounts), path)
+}
+
+// Finds filesystem type of the path in /proc/self/mountinfo contents (see proc_pid_mountinfo(5) for the
+// format)
+#[cfg(not(target_os = "macos"))]
+fn get_mount_filesystem_type(mounts: &str, path: &Path) -> GenericResult<String> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut result = None;
+
+    for line in mounts.lines() {
+        let (mount_point, filesystem_type) = line.split_once(" - ")
+            .and_then(|(mount, filesystem)| Some((mount.split(' ').nth(4)?, filesystem.split(' ').next()?)))
+            .ok_or_else(|| format!("Got an invalid /proc/self/mountinfo line: {:?}", line))?;
+
+        // Mounts are listed in mount order, so the last matching one shadows the previous ones (including
+        // the mounts below its mount point)
+        let mount_point = PathBuf::from(OsStr::from_bytes(&unescape_mount_path(mount_point)));
+        if path.starts_with(&mount_point) {
+            result = Some(filesystem_type);
+        }
+    }
+
+    Ok(result.ok_or_else(|| format!("Unable to find {:?} mount point", path))?.to_owned())
+}
+
+// Decodes octal escapes (\040 for space, etc.) of mount paths
+#[cfg(not(target_os = "macos"))]
+fn unescape_mount_path(path: &str) -> Vec<u8> {
+    let path = path.as_bytes();
+    let mut result = Vec::with_capacity(path.len());
+    let mut index = 0;
+
+    while index < path.len() {
+        if path[index] == b'\\' && let Some(code) = path.get(index + 1..index + 4)
+            .and_then(|code| std::str::from_utf8(code).ok())
+            .and_then(|code| u8::from_str_radix(code, 8).ok())
+        {
+            result.push(code);
+            index += 4;
+        } else {
+            result.push(path[index]);
+            index += 1;
+        }
+    }
+
+    result
+}
+
+#[cfg(target_os = "macos")]
+pub fn get_filesystem_type(path: &Path) -> GenericResult<String> {
+    let stat = sys::statfs::statfs(path).map_err(|e| format!("Unable to statfs() {:?}: {}", path, e))?;
+    Ok(stat.filesystem_type_name().to_owned())
+}
+
 // To be sure that data at least will be written. Used when we don't want fsync.
 pub fn close_file(file: File) -> nix::Result<()> {
     unistd::close(file.into_raw_fd()).or_else(|err| {
@@ -114,4 +210,47 @@ pub fn terminate_process(name: &str, pid: pid_t, timeout: Duration) -> EmptyResu
     debug!("Successfully terminated {}.", name);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn mount_path_unescaping() {
+        assert_eq!(unescape_mount_path(r"/mnt/with\040space\134\12"), b"/mnt/with space\\\\12");
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn mount_filesystem_type() {
+        let mounts = indoc!(r"
+            22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+            23 22 0:21 / /proc rw,nosuid shared:2 - proc proc rw
+            24 22 0:22 / /mnt/with\040space rw,relatime shared:3 - tmpfs tmpfs rw
+            25 22 8:17 / /data rw,relatime shared:4 - xfs /dev/sdb1 rw
+            26 25 0:23 / /data/nested rw,relatime shared:5 - tmpfs tmpfs rw
+            27 22 0:24 / /data rw,relatime shared:6 - btrfs /dev/sdc1 rw
+            28 22 0:25 / /proc rw,nosuid shared:7 - fuse.sshfs host:/ rw
+        ");
+
+        for (path, expected) in [
+            ("/", "ext4"),
+            ("/home/user", "ext4"),
+            ("/mnt/with space/file", "tmpfs"),
+            ("/mnt/with", "ext4"),
+            ("/processes", "ext4"),
+            // Shadowed by the following mounts
+            ("/proc/self", "fuse.sshfs"),
+            ("/data/nested/file", "btrfs"),
+        ] {
+            assert_eq!(get_mount_filesystem_type(mounts, Path::new(path)).unwrap(), expected, "{}", path);
+        }
+
+        assert!(get_mount_filesystem_type("22 1 8:1 / / rw - ext4 /dev/sda1 rw", Path::new("relative")).is_err());
+        assert!(get_mount_filesystem_type("invalid line", Path::new("/")).is_err());
+    }
+}
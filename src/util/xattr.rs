@@ -0,0 +1,257 @@
+// Extended attributes support. POSIX ACLs are stored by Linux in system.posix_acl_* extended
+// attributes, so they are handled the same way.
+//
+// In the archive extended attributes are stored in PAX extended headers using SCHILY.xattr.* records
+// like GNU tar does. tar crate isn't able to parse records with newlines in values, so such values are
+// stored hex-encoded in VSB.xattr.hex.* records which are ignored by other tar implementations.
+
+use std::ffi::CString;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use log::warn;
+use tar::{Builder, Entry, EntryType, Header};
+
+use crate::core::{EmptyResult, GenericResult};
+
+const PAX_PREFIX: &str = "SCHILY.xattr.";
+const PAX_HEX_PREFIX: &str = "VSB.xattr.hex.";
+
+pub type Xattrs = Vec<(String, Vec<u8>)>;
+
+// Reads extended attributes of the file without following symlinks
+pub fn read(path: &Path) -> io::Result<Xattrs> {
+    let c_path = c_path(path)?;
+    let mut xattrs = Xattrs::new();
+
+    let names = match read_buffer(|buf, size| unsafe { sys::list(&c_path, buf, size) }) {
+        Ok(names) => names,
+        Err(err) if err.raw_os_error() == Some(libc::ENOTSUP) => return Ok(xattrs),
+        Err(err) => return Err(err),
+    };
+
+    for name in names.split(|&byte| byte == 0).filter(|name| !name.is_empty()) {
+        let Some(name) = std::str::from_utf8(name).ok().filter(|name| !name.contains(['=', '\n'])) else {
+            warn!("Skipping {:?} extended attribute of {:?}: invalid name.",
+                String::from_utf8_lossy(name), path);
+            continue;
+        };
+
+        let c_name = CString::new(name).unwrap();
+        let value = match read_buffer(|buf, size| unsafe { sys::get(&c_path, &c_name, buf, size) }) {
+            Ok(value) => value,
+            // The attribute has been deleted after we've got the list
+            Err(err) if err.raw_os_error() == Some(sys::ENOATTR) => continue,
+            Err(err) => return Err(err),
+        };
+
+        xattrs.push((name.to_owned(), value));
+    }
+
+    Ok(xattrs)
+}
+
+// Sets extended attributes skipping the ones we aren't permitted to set or which aren't supported by
+// the filesystem
+pub fn set(path: &Path, xattrs: &Xattrs) -> EmptyResult {
+    let c_path = c_path(path)?;
+
+    for (name, value) in xattrs {
+        let c_name = CString::new(name.as_str()).map_err(|_| format!(
+            "Got an invalid extended attribute name for {:?}: {:?}", path, name))?;
+
+        if unsafe { sys::set(&c_path, &c_name, value) } != 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EPERM | libc::EACCES | libc::ENOTSUP) => {
+                    warn!("Unable to set {:?} extended attribute for {:?}: {}.", name, path, err);
+                },
+                _ => return Err!("Unable to set {:?} extended attribute for {:?}: {}", name, path, err),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Appends PAX extended header with the attributes which will be applied to the next archive entry
+pub fn append_pax<W: Write>(archive: &mut Builder<W>, xattrs: &Xattrs) -> io::Result<()> {
+    if xattrs.is_empty() {
+        return Ok(());
+    }
+
+    let mut data = Vec::new();
+
+    for (name, value) in xattrs {
+        let (prefix, value) = if value.contains(&b'\n') {
+            (PAX_HEX_PREFIX, hex::encode(value).into_bytes())
+        } else {
+            (PAX_PREFIX, value.clone())
+        };
+
+        // The record length includes the length field itself
+        let record_size = prefix.len() + name.len() + value.len() + 3;
+        let mut size = record_size + record_size.to_string().len();
+        if size.to_string().len() != record_size.to_string().len() {
+            size += 1;
+        }
+
+        write!(data, "{} {}{}=", size, prefix, name)?;
+        data.extend(value);
+        data.push(b'\n');
+    }
+
+    let mut header = Header::new_ustar();
+    header.set_entry_type(EntryType::XHeader);
+    header.set_path("././@PaxHeader")?;
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+
+    archive.append(&header, data.as_slice())
+}
+
+// Returns extended attributes from PAX extended header of the archive entry
+pub fn read_pax<R: Read>(entry: &mut Entry<R>) -> GenericResult<Xattrs> {
+    let mut xattrs = Xattrs::new();
+
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok(xattrs);
+    };
+
+    for extension in extensions {
+        let extension = extension?;
+        let key = extension.key().map_err(|_| format!(
+            "Got an invalid PAX record key: {:?}", String::from_utf8_lossy(extension.key_bytes())))?;
+
+        if let Some(name) = key.strip_prefix(PAX_PREFIX) {
+            xattrs.push((name.to_owned(), extension.value_bytes().to_vec()));
+        } else if let Some(name) = key.strip_prefix(PAX_HEX_PREFIX) {
+            let value = hex::decode(extension.value_bytes()).map_err(|_| format!(
+                "Got an invalid {:?} extended attribute value", name))?;
+            xattrs.push((name.to_owned(), value));
+        }
+    }
+
+    Ok(xattrs)
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| io::Error::new(
+        io::ErrorKind::InvalidInput, "path contains a null byte"))
+}
+
+// Calls *xattr() function which returns a variable size data
+fn read_buffer<F>(func: F) -> io::Result<Vec<u8>>
+    where F: Fn(*mut u8, usize) -> libc::ssize_t
+{
+    loop {
+        let size = func(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        } else if size == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buffer = vec![0; size as usize];
+        let size = func(buffer.as_mut_ptr(), buffer.len());
+
+        if size >= 0 {
+            buffer.truncate(size as usize);
+            return Ok(buffer);
+        }
+
+        // The data has grown between the calls
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    }
+}
+
+// All functions operate on the symlink itself if the path points to a symlink
+#[cfg(not(target_os = "macos"))]
+mod sys {
+    use std::ffi::CStr;
+
+    pub const ENOATTR: i32 = libc::ENODATA;
+
+    pub unsafe fn list(path: &CStr, buf: *mut u8, size: usize) -> libc::ssize_t {
+        unsafe { libc::llistxattr(path.as_ptr(), buf as *mut libc::c_char, size) }
+    }
+
+    pub unsafe fn get(path: &CStr, name: &CStr, buf: *mut u8, size: usize) -> libc::ssize_t {
+        unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf as *mut libc::c_void, size) }
+    }
+
+    pub unsafe fn set(path: &CStr, name: &CStr, value: &[u8]) -> libc::c_int {
+        unsafe {
+            libc::lsetxattr(path.as_ptr(), name.as_ptr(), value.as_ptr() as *const libc::c_void, value.len(), 0)
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::ffi::CStr;
+
+    pub const ENOATTR: i32 = libc::ENOATTR;
+
+    pub unsafe fn list(path: &CStr, buf: *mut u8, size: usize) -> libc::ssize_t {
+        unsafe { libc::listxattr(path.as_ptr(), buf as *mut libc::c_char, size, libc::XATTR_NOFOLLOW) }
+    }
+
+    pub unsafe fn get(path: &CStr, name: &CStr, buf: *mut u8, size: usize) -> libc::ssize_t {
+        unsafe {
+            libc::getxattr(
+                path.as_ptr(), name.as_ptr(), buf as *mut libc::c_void, size, 0, libc::XATTR_NOFOLLOW)
+        }
+    }
+
+    pub unsafe fn set(path: &CStr, name: &CStr, value: &[u8]) -> libc::c_int {
+        unsafe {
+            libc::setxattr(
+                path.as_ptr(), name.as_ptr(), value.as_ptr() as *const libc::c_void, value.len(), 0,
+                libc::XATTR_NOFOLLOW)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use tar::Archive;
+
+    use super::*;
+
+    #[test]
+    fn pax() {
+        let xattrs = vec![
+            ("user.empty".to_owned(), Vec::new()),
+            ("user.binary".to_owned(), vec![0, b'\n', b'=', 255]),
+            ("security.capability".to_owned(), vec![b'x'; 90]),
+        ];
+
+        let mut archive = Builder::new(Vec::new());
+        for (path, xattrs) in [("with-xattrs", &xattrs), ("without-xattrs", &Xattrs::new())] {
+            append_pax(&mut archive, xattrs).unwrap();
+            let mut header = Header::new_gnu();
+            header.set_size(0);
+            archive.append_data(&mut header, path, io::empty()).unwrap();
+        }
+        let data = archive.into_inner().unwrap();
+
+        let mut archive = Archive::new(Cursor::new(data));
+        let entries = archive.entries().unwrap().map(|entry| {
+            let mut entry = entry.unwrap();
+            (entry.path().unwrap().to_str().unwrap().to_owned(), read_pax(&mut entry).unwrap())
+        }).collect::<Vec<_>>();
+
+        assert_eq!(entries, [
+            ("with-xattrs".to_owned(), xattrs),
+            ("without-xattrs".to_owned(), Xattrs::new()),
+        ]);
+    }
+}